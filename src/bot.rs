@@ -0,0 +1,151 @@
+//! Outgoing bot actions. Handlers send their requests through [`Bot`] rather than
+//! the live API, so that they can be driven by a stand-in as well.
+
+use futures::future::{BoxFuture, FutureExt};
+use telegram_bot::*;
+
+use crate::types::GameMessage;
+
+#[cfg(test)]
+pub(crate) mod fake;
+
+pub(crate) trait Bot: Clone + Send + Sync + 'static {
+    /// Sends the request, like [`Api::send`] does.
+    fn send<'a, Req>(
+        &'a self,
+        request: Req,
+    ) -> BoxFuture<'a, Result<<Req::Response as ResponseType>::Type, Error>>
+    where
+        Req: Request + 'a;
+
+    /// Downloads the contents of a document sent to the chat, `None` if it can't be fetched.
+    fn download<'a>(
+        &'a self,
+        document: &'a Document,
+    ) -> BoxFuture<'a, Result<Option<Vec<u8>>, Error>>;
+}
+
+/// The bot talking to Telegram. Unlike [`Api`] it keeps the token, which is a part of
+/// the download links of the files.
+#[derive(Clone)]
+pub(crate) struct TelegramBot {
+    api: Api,
+    token: String,
+}
+
+impl TelegramBot {
+    pub fn new(token: String) -> Self {
+        TelegramBot {
+            api: Api::new(&token),
+            token,
+        }
+    }
+
+    pub fn stream(&self) -> UpdatesStream {
+        self.api.stream()
+    }
+}
+
+impl Bot for TelegramBot {
+    fn send<'a, Req>(
+        &'a self,
+        request: Req,
+    ) -> BoxFuture<'a, Result<<Req::Response as ResponseType>::Type, Error>>
+    where
+        Req: Request + 'a,
+    {
+        self.api.send(request).boxed()
+    }
+
+    fn download<'a>(
+        &'a self,
+        document: &'a Document,
+    ) -> BoxFuture<'a, Result<Option<Vec<u8>>, Error>> {
+        async move {
+            let file = self.api.send(document.get_file()).await?;
+            let url = match file.get_url(&self.token) {
+                Some(url) => url,
+                None => return Ok(None),
+            };
+
+            let body = match reqwest::get(&url).await {
+                Ok(response) => response.bytes().await,
+                Err(e) => Err(e),
+            };
+            match body {
+                Ok(body) => Ok(Some(body.to_vec())),
+                Err(e) => {
+                    eprintln!("File download error: {}", e);
+                    Ok(None)
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+/// A message left to send once the lock on the games is released, so that the updates
+/// of every chat don't wait on Telegram along with it.
+pub(crate) enum Outgoing {
+    /// Text put in place of a poll that is closed.
+    ClosedPoll {
+        poll_msg: GameMessage,
+        text: String,
+        parse_mode: Option<ParseMode>,
+    },
+    Reply {
+        msg: GameMessage,
+        text: String,
+    },
+    Text {
+        chat: ChatId,
+        text: String,
+    },
+    /// The game message brought up to date, which is pinned again.
+    GameMessage {
+        msg: GameMessage,
+        text: String,
+    },
+}
+
+/// Sends the messages in order.
+pub(crate) async fn send_all<B: Bot>(api: &B, outgoing: Vec<Outgoing>) -> Result<(), Error> {
+    for outgoing in outgoing {
+        match outgoing {
+            Outgoing::ClosedPoll {
+                poll_msg,
+                text,
+                parse_mode,
+            } => {
+                let poll_msg: MessageOrChannelPost = poll_msg.into();
+                let mut edit = poll_msg.edit_text(text);
+                if let Some(parse_mode) = parse_mode {
+                    edit.parse_mode(parse_mode);
+                }
+                // The poll may be gone already, the vote is resolved anyway
+                let _ = api.send(edit).await;
+            }
+
+            Outgoing::Reply { msg, text } => {
+                api.send(MessageOrChannelPost::from(msg).text_reply(text))
+                    .await?;
+            }
+
+            Outgoing::Text { chat, text } => {
+                api.send(chat.text(text)).await?;
+            }
+
+            Outgoing::GameMessage { msg, text } => {
+                let message: MessageOrChannelPost = msg.into();
+                let response = api
+                    .send(message.edit_text(text).parse_mode(ParseMode::Markdown))
+                    .await?;
+
+                // Ignore the error as we can't pin the message if it's pinned already
+                let _ = api.send(response.pin()).await;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,341 @@
+//! A stand-in for Telegram in tests. [`FakeBot`] answers the requests of the handlers
+//! and keeps them to be checked, [`TestChat`] sends the handlers scripted updates.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::{json, Value};
+use telegram_bot::*;
+
+use crate::bot::Bot;
+use crate::time::now;
+use crate::types::{Game, GameUser};
+use crate::{process_update, GAMES};
+
+/// A request the bot has sent.
+#[derive(Clone, Debug)]
+pub(crate) struct Sent {
+    /// Name of the API method.
+    pub method: &'static str,
+    pub chat_id: i64,
+    /// The message sent, edited or pinned.
+    pub message_id: i64,
+    /// The message replied to.
+    pub reply_to: Option<i64>,
+    pub text: String,
+    /// Callback data of the inline keyboard buttons.
+    pub buttons: Vec<String>,
+}
+
+#[derive(Default)]
+struct State {
+    last_message_id: i64,
+    /// Every message of the chats, the bot's and the scripted ones, as Telegram sends them.
+    messages: HashMap<i64, Value>,
+    requests: Vec<Sent>,
+    files: HashMap<String, Vec<u8>>,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct FakeBot {
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeBot {
+    /// Messages sent, new messages and documents.
+    pub fn sent(&self) -> Vec<Sent> {
+        self.requests(&["sendMessage", "sendDocument"])
+    }
+
+    pub fn edited(&self) -> Vec<Sent> {
+        self.requests(&["editMessageText", "editMessageReplyMarkup"])
+    }
+
+    pub fn pinned(&self) -> Vec<Sent> {
+        self.requests(&["pinChatMessage"])
+    }
+
+    /// Answers to the pressed buttons.
+    pub fn answers(&self) -> Vec<Sent> {
+        self.requests(&["answerCallbackQuery"])
+    }
+
+    /// Makes the document with the id downloadable.
+    pub fn add_file(&self, file_id: &str, contents: Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        state.files.insert(file_id.to_owned(), contents);
+    }
+
+    fn requests(&self, methods: &[&str]) -> Vec<Sent> {
+        let state = self.state.lock().unwrap();
+        state
+            .requests
+            .iter()
+            .filter(|sent| methods.contains(&sent.method))
+            .cloned()
+            .collect()
+    }
+
+    /// Keeps the message under a new id, returns the id.
+    fn add_message(&self, mut message: Value) -> i64 {
+        let mut state = self.state.lock().unwrap();
+        state.last_message_id += 1;
+        let id = state.last_message_id;
+        message["message_id"] = json!(id);
+        state.messages.insert(id, message);
+        id
+    }
+
+    fn message(&self, id: i64) -> Value {
+        let state = self.state.lock().unwrap();
+        state.messages.get(&id).cloned().expect("known message")
+    }
+
+    /// Result of the request, as Telegram would answer it.
+    fn answer(&self, request: &HttpRequest) -> Value {
+        let params = match &request.body {
+            Body::Json(body) => serde_json::from_str(body).expect("JSON request"),
+            _ => json!({}),
+        };
+        let chat_id = params["chat_id"].as_i64().unwrap_or_default();
+        let text = params["text"]
+            .as_str()
+            .or_else(|| params["caption"].as_str())
+            .unwrap_or_default()
+            .to_owned();
+
+        let (message_id, result) = match request.name() {
+            "sendMessage" | "sendDocument" => {
+                let message = json!({
+                    "date": now(),
+                    "chat": chat(chat_id),
+                    "from": user(&bot_user()),
+                    "text": text,
+                });
+                let id = self.add_message(message);
+                (id, self.message(id))
+            }
+
+            "editMessageText" | "editMessageReplyMarkup" => {
+                let id = params["message_id"].as_i64().expect("edited message id");
+                let mut state = self.state.lock().unwrap();
+                let message = state.messages.get_mut(&id).expect("edited message");
+                if request.name() == "editMessageText" {
+                    message["text"] = json!(text);
+                }
+                (id, message.clone())
+            }
+
+            "pinChatMessage" | "deleteMessage" | "answerCallbackQuery" => (
+                params["message_id"].as_i64().unwrap_or_default(),
+                json!(true),
+            ),
+
+            name => panic!("Unexpected request {}", name),
+        };
+
+        let buttons = params["reply_markup"]["inline_keyboard"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .flat_map(|row| row.as_array().cloned().unwrap_or_default())
+            .filter_map(|button| button["callback_data"].as_str().map(str::to_owned))
+            .collect();
+        self.state.lock().unwrap().requests.push(Sent {
+            method: request.name(),
+            chat_id,
+            message_id,
+            reply_to: params["reply_to_message_id"].as_i64(),
+            text,
+            buttons,
+        });
+
+        result
+    }
+}
+
+impl Bot for FakeBot {
+    fn send<'a, Req>(
+        &'a self,
+        request: Req,
+    ) -> BoxFuture<'a, Result<<Req::Response as ResponseType>::Type, Error>>
+    where
+        Req: Request + 'a,
+    {
+        let request = request.serialize().expect("request");
+        let body = json!({ "ok": true, "result": self.answer(&request) });
+        let response = HttpResponse {
+            body: Some(body.to_string().into_bytes()),
+        };
+
+        async move { Ok(<Req::Response as ResponseType>::deserialize(response).expect("response")) }
+            .boxed()
+    }
+
+    fn download<'a>(
+        &'a self,
+        document: &'a Document,
+    ) -> BoxFuture<'a, Result<Option<Vec<u8>>, Error>> {
+        let state = self.state.lock().unwrap();
+        let contents = state.files.get(&document.file_id).cloned();
+        async move { Ok(contents) }.boxed()
+    }
+}
+
+/// A group where the members send messages and press buttons. The updates go through
+/// the same handlers as those coming from Telegram.
+pub(crate) struct TestChat {
+    pub id: i64,
+    pub bot: FakeBot,
+}
+
+impl TestChat {
+    /// Chats of the tests share the games, each test needs a chat of its own.
+    pub fn new(id: i64) -> Self {
+        TestChat {
+            id,
+            bot: Default::default(),
+        }
+    }
+
+    /// Sends a text message, returns its id.
+    pub async fn send(&self, from: &GameUser, text: &str) -> i64 {
+        self.post(from, json!({ "text": text }), None).await
+    }
+
+    /// Sends a text message in reply to another one.
+    pub async fn reply(&self, from: &GameUser, text: &str, to: i64) -> i64 {
+        self.post(from, json!({ "text": text }), Some(to)).await
+    }
+
+    /// Sends a video, `file` tells apart the clips.
+    pub async fn video(&self, from: &GameUser, file: &str, caption: Option<&str>) -> i64 {
+        let video = json!({
+            "video": {
+                "file_id": file,
+                "file_unique_id": file,
+                "width": 1280,
+                "height": 720,
+                "duration": 5,
+            },
+            "caption": caption,
+        });
+        self.post(from, video, None).await
+    }
+
+    pub async fn photo(&self, from: &GameUser, file: &str) -> i64 {
+        let photo = json!({
+            "photo": [{
+                "file_id": file,
+                "file_unique_id": file,
+                "width": 1280,
+                "height": 720,
+            }],
+        });
+        self.post(from, photo, None).await
+    }
+
+    /// Sends a document, which the bot can download then.
+    pub async fn document(&self, from: &GameUser, file: &str, contents: Vec<u8>) -> i64 {
+        self.bot.add_file(file, contents);
+        let document = json!({
+            "document": {
+                "file_id": file,
+                "file_unique_id": file,
+                "file_name": format!("{}.yaml", file),
+            },
+        });
+        self.post(from, document, None).await
+    }
+
+    /// Presses the button with the callback data under the message.
+    pub async fn press(&self, from: &GameUser, message_id: i64, data: &str) {
+        let update = json!({
+            "update_id": message_id,
+            "callback_query": {
+                "id": format!("{}:{}", message_id, from.id),
+                "from": user(from),
+                "message": self.bot.message(message_id),
+                "chat_instance": self.id.to_string(),
+                "data": data,
+            },
+        });
+        self.process(update).await;
+    }
+
+    /// The message last sent to the chat by the bot.
+    pub fn last_sent(&self) -> Sent {
+        self.bot
+            .sent()
+            .into_iter()
+            .rev()
+            .find(|sent| sent.chat_id == self.id)
+            .expect("a message sent to the chat")
+    }
+
+    pub async fn game(&self) -> Game {
+        let games = GAMES.lock().await;
+        games.get(&self.id.to_string()).cloned().unwrap_or_default()
+    }
+
+    async fn post(&self, from: &GameUser, mut message: Value, reply_to: Option<i64>) -> i64 {
+        message["date"] = json!(now());
+        message["chat"] = chat(self.id);
+        message["from"] = user(from);
+        if let Some(reply_to) = reply_to {
+            message["reply_to_message"] = self.bot.message(reply_to);
+        }
+
+        let id = self.bot.add_message(message);
+        let update = json!({ "update_id": id, "message": self.bot.message(id) });
+        self.process(update).await;
+        id
+    }
+
+    async fn process(&self, update: Value) {
+        let update = serde_json::from_value(update).expect("update");
+        process_update(self.bot.clone(), update).await;
+    }
+}
+
+/// A member of the test chats.
+pub(crate) fn member(id: i64, first_name: &str) -> GameUser {
+    GameUser {
+        id,
+        first_name: first_name.to_owned(),
+        username: None,
+    }
+}
+
+/// The member who may run the admin commands.
+pub(crate) fn admin() -> GameUser {
+    GameUser {
+        username: Some("eupn1337".to_owned()),
+        ..member(1000, "Admin")
+    }
+}
+
+fn bot_user() -> GameUser {
+    GameUser {
+        username: Some("GameOfSk8Bot".to_owned()),
+        ..member(1, "Game of Skate")
+    }
+}
+
+fn user(user: &GameUser) -> Value {
+    json!({
+        "id": user.id,
+        "is_bot": user.id == bot_user().id,
+        "first_name": user.first_name,
+        "username": user.username,
+    })
+}
+
+fn chat(id: i64) -> Value {
+    if id < 0 {
+        json!({ "id": id, "type": "group", "title": "Game of Skate" })
+    } else {
+        json!({ "id": id, "type": "private", "first_name": "Skater" })
+    }
+}
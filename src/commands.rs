@@ -1,2 +1,11 @@
 pub(crate) mod challenge;
+pub(crate) mod deadline;
+pub(crate) mod duel;
+pub(crate) mod lifecycle;
+pub(crate) mod proofs;
 pub(crate) mod randomtrick;
+pub(crate) mod scoring;
+pub(crate) mod settings;
+pub(crate) mod snapshots;
+pub(crate) mod transfer;
+pub(crate) mod withdraw;
@@ -1,26 +1,48 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
 use telegram_bot::*;
 
+use crate::bot::{self, Bot, Outgoing};
+use crate::commands::duel;
+use crate::commands::lifecycle::FINISHED_REPLY;
+use crate::time::{now, HOUR_SECS};
 use crate::types::*;
-use crate::{crop_letters, dropbox, update_game_message};
+use crate::voting::{self, Tally, VotingPolicy};
+use crate::{crop_letters, game_message_update, is_admin, persistence, update_game_message, GAMES};
 
-pub(crate) async fn process_challenge_command(
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+pub(crate) async fn process_challenge_command<B: Bot>(
     games: &mut HashMap<String, Game>,
-    api: &mut Api,
+    api: &mut B,
     message: &Message,
 ) -> Result<(), Error> {
     let game = games
         .entry(message.chat.id().to_string())
         .or_insert(Default::default());
 
+    // Repeats in a duel are disputed the same way, whatever the state of the game
+    if let Some(MessageOrChannelPost::Message(reply)) = message.reply_to_message.as_deref() {
+        if duel::is_landed_trick(game, &reply.clone().into()) {
+            duel::dispute_landing(game, api, message, reply).await?;
+            persistence::mark_dirty(&message.chat);
+            return Ok(());
+        }
+    }
+
+    if game.finished() {
+        api.send(message.text_reply(FINISHED_REPLY)).await?;
+        return Ok(());
+    }
+
     if let Some(reply) = &message.reply_to_message {
         if let MessageOrChannelPost::Message(ref reply) = **reply {
             let msg: GameMessage = reply.clone().into();
             if let Some((user, participant, proof)) = game.find_participant_and_proof_by_msg(&msg) {
-                if let Some(challenge) = &game.proof_challenge {
+                if let Some(challenge) = game.proof_challenges.get(&msg) {
                     api.send(message.text_reply(
-                        format!("Голосование по трюку [уже в процессе](https://t.me/c/{chat_id}/{message_id}). \
+                        format!("Голосование по этому пруфу [уже в процессе](https://t.me/c/{chat_id}/{message_id}). \
                         Нужно дождаться его завершения.",
                             chat_id = crop_letters(&challenge.poll_msg.chat_id.to_string(), 4),
                             message_id = challenge.poll_msg.id
@@ -31,7 +53,7 @@ pub(crate) async fn process_challenge_command(
                     return Ok(());
                 }
                 challenge_proof(game, api, &reply, &user, participant, proof).await?;
-                dropbox::save_games(&games).await;
+                persistence::mark_dirty(&message.chat);
             } else {
                 api.send(
                     message.text_reply("Это сообщение не представляет собою доказательство трюка."),
@@ -44,9 +66,9 @@ pub(crate) async fn process_challenge_command(
     Ok(())
 }
 
-pub(crate) async fn process_callback_query(
+pub(crate) async fn process_callback_query<B: Bot>(
     games: &mut HashMap<String, Game>,
-    api: Api,
+    api: B,
     cb: CallbackQuery,
 ) -> Result<(), Error> {
     if let Some(message) = &cb.message {
@@ -55,146 +77,362 @@ pub(crate) async fn process_callback_query(
                 .entry(message.chat.id().to_string())
                 .or_insert(Default::default());
 
-            let tricks = if let Some(challenge) = &game.proof_challenge {
-                challenge
-                    .proof
-                    .tricks_proven
-                    .iter()
-                    .flat_map(|trick_no| game.trick_by_number(*trick_no))
-                    .map(|trick| format!("\"{}\"", trick.name))
-                    .collect::<Vec<_>>()
-                    .join(", ")
-            } else {
-                "".to_owned()
+            // The vote, then the ids of the chat, the challenged participant and the proof.
+            // The proof is looked up in the chat of the poll, which is the current one
+            // even if the chat was migrated after the poll was posted.
+            let data = cb
+                .data
+                .as_deref()
+                .unwrap_or("")
+                .split(",")
+                .collect::<Vec<_>>();
+            let (yes_no, proof_msg) = match data.get(3).and_then(|id| id.parse::<i64>().ok()) {
+                Some(proof_msg_id) => (
+                    data[0],
+                    GameMessage {
+                        id: proof_msg_id,
+                        chat_id: i64::from(message.chat.id()),
+                    },
+                ),
+                None => return Ok(()),
             };
 
-            let mut is_resolved = false;
-            let mut should_update_game_message = false;
-            if let Some(ref mut challenge) = game.proof_challenge {
-                if let Some(data) = &cb.data {
-                    let data = data.split(",").collect::<Vec<_>>();
-                    let yes_no = data[0];
-
-                    let user: GameUser = cb.from.clone().into();
-                    if !game.participants.contains_key(&user) {
-                        api.send(cb.answer("Голосовать могут только участники игры."))
-                            .await?;
-                        return Ok(());
-                    }
+            let (tricks, num_allowed) = match game.proof_challenges.get(&proof_msg) {
+                Some(challenge) => (
+                    format_tricks(game, &challenge.proof),
+                    num_allowed_voters(game, &challenge.user),
+                ),
+                None => {
+                    api.send(cb.answer("Голосование уже завершено.")).await?;
+                    return Ok(());
+                }
+            };
 
-                    if challenge.voters.contains(&user) {
-                        api.send(cb.answer("Ты уже проголосовал.")).await?;
-                        return Ok(());
-                    }
-                    challenge.voters.insert(user.clone());
+            let user: GameUser = cb.from.clone().into();
+            // With the veto an admin decides even without playing
+            let is_veto = game.settings.voting == VotingPolicy::AdminVeto && is_admin(&cb.from);
+            let is_voter = game.participants.contains_key(&user);
+            let mut verdict = None;
+            if let Some(challenge) = game.proof_challenges.get_mut(&proof_msg) {
+                if !is_voter && !is_veto {
+                    api.send(cb.answer("Голосовать могут только участники игры."))
+                        .await?;
+                    return Ok(());
+                }
 
-                    match yes_no {
-                        "yes" => {
-                            challenge.num_yes += 1;
-                        }
-                        "no" => {
-                            challenge.num_no += 1;
-                        }
+                if user == challenge.user && !game.settings.owner_votes {
+                    api.send(
+                        cb.answer("По правилам этой игры автор пруфа не голосует (/settings)."),
+                    )
+                    .await?;
+                    return Ok(());
+                }
 
-                        _ => return Ok(()),
+                let vote = match yes_no {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return Ok(()),
+                };
+
+                // Pressing the same button again takes the vote back. The vote of an admin
+                // outside the game decides at once and isn't one of the counted ones.
+                let answer = match challenge.votes.get(&user) {
+                    _ if !is_voter => "Твой голос принят.",
+                    Some(&previous) if previous == vote => {
+                        challenge.votes.shift_remove(&user);
+                        "Твой голос отменен."
                     }
+                    Some(_) => {
+                        challenge.votes.insert(user.clone(), vote);
+                        "Твой голос изменен."
+                    }
+                    None => {
+                        challenge.votes.insert(user.clone(), vote);
+                        "Твой голос принят."
+                    }
+                };
+                api.send(cb.answer(answer)).await?;
+
+                let tally = Tally {
+                    num_yes: challenge.num_yes(),
+                    num_no: challenge.num_no(),
+                    num_allowed,
+                    admin_vote: match (is_veto, is_voter) {
+                        (true, true) => challenge.votes.get(&user).copied(),
+                        (true, false) => Some(vote),
+                        (false, _) => None,
+                    },
+                };
+                if let Some(is_accepted) = voting::verdict(&game.settings, &tally) {
+                    let msg = format_verdict(&tricks, challenge, verdict_caption(is_accepted));
+                    api.send(message.edit_text(msg).parse_mode(ParseMode::MarkdownV2))
+                        .await?;
+
+                    verdict = Some(is_accepted);
+                } else {
+                    let msg = format!(
+                        "На этом видео выполнены эти трюки: {}?\n\n_Проголосовали: {}_",
+                        tricks,
+                        format_voters(challenge)
+                    );
+                    api.send(message.edit_text(msg).parse_mode(ParseMode::MarkdownV2))
+                        .await?;
+
+                    let keyboard = build_poll_keyboard(
+                        "",
+                        challenge.poll_msg.chat_id,
+                        challenge.user.id,
+                        challenge.proof.msg.id,
+                        Some(challenge.num_yes()),
+                        Some(challenge.num_no()),
+                    );
+
+                    api.send(message.edit_reply_markup(Some(keyboard))).await?;
+                }
+            }
 
-                    api.send(cb.answer("Твой голос принят.")).await?;
-
-                    let voters = challenge
-                        .voters
-                        .iter()
-                        .map(|voter| format!("[{}](tg://user?id={})", voter.first_name, voter.id))
-                        .collect::<Vec<_>>()
-                        .join(", ");
-
-                    if challenge.voters.len() > game.participants.len() / 2 {
-                        let result = if challenge.num_yes >= challenge.num_no {
-                            (true, "✅ ПРИНЯТО")
-                        } else {
-                            (false, "❌ ПЕРЕДЕЛАТЬ")
-                        };
-                        let msg = format!(
-                            "На этом видео выполнены эти трюки: {}?\n\nВердикт:*{}*\n\n_Проголосовали: {}_\n\n{} 👍, {} 👎",
-                            tricks, result.1, voters, challenge.num_yes, challenge.num_no,
-                        );
-                        api.send(message.edit_text(msg).parse_mode(ParseMode::MarkdownV2))
-                            .await?;
-
-                        if !result.0 {
-                            if let Some(participant) = game.participants.get_mut(&user) {
-                                if let Some(idx) = participant
-                                    .proofs
-                                    .iter()
-                                    .position(|proof| *proof == challenge.proof)
-                                {
-                                    participant.proofs.remove(idx);
-                                }
-
-                                api.send(
-                                    MessageOrChannelPost::from(challenge.proof.msg.clone())
-                                        .text_reply("Это доказательство удалено."),
-                                )
-                                .await?;
-                                should_update_game_message = true;
-                            }
-                        }
-
-                        is_resolved = true;
-                    } else {
-                        let msg = format!(
-                            "На этом видео выполнены эти трюки: {}?\n\n_Проголосовали: {}_",
-                            tricks, voters
-                        );
-                        api.send(message.edit_text(msg).parse_mode(ParseMode::MarkdownV2))
-                            .await?;
-
-                        let keyboard = build_poll_keyboard(
-                            challenge.poll_msg.chat_id,
-                            challenge.user.id,
-                            challenge.proof.msg.id,
-                            Some(challenge.num_yes),
-                            Some(challenge.num_no),
-                        );
-
-                        api.send(message.edit_reply_markup(Some(keyboard))).await?;
+            if let Some(is_accepted) = verdict {
+                if let Some(challenge) = game.proof_challenges.shift_remove(&proof_msg) {
+                    let mut outgoing = vec![];
+                    if !is_accepted
+                        && reject_proof(game, &challenge.user, &challenge.proof, &mut outgoing)
+                    {
+                        bot::send_all(&api, outgoing).await?;
+                        update_game_message(&mut api.clone(), &message.chat, game).await?;
                     }
                 }
             }
 
-            if is_resolved {
-                game.proof_challenge = None;
+            persistence::mark_dirty(&message.chat);
+        }
+    }
 
-                if should_update_game_message {
-                    update_game_message(&mut api.clone(), &message.chat, game).await?;
+    Ok(())
+}
+
+/// Starts the background task that resolves the challenges, and the votes on the repeats
+/// in duels, once their voting time is out. The polls keep the time they were opened at,
+/// so a restart doesn't extend it.
+pub(crate) fn spawn<B: Bot>(api: B) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::delay_for(CHECK_INTERVAL).await;
+
+            // Only resolve the votes under the lock, the chats are told without it
+            let outgoing = {
+                let mut games = GAMES.lock().await;
+                games
+                    .iter_mut()
+                    .map(|(chat_id, game)| {
+                        let mut outgoing = vec![];
+                        let rejected = close_expired_challenges(chat_id, game, &mut outgoing);
+                        let resolved = duel::close_expired_dispute(chat_id, game, &mut outgoing);
+                        if rejected || resolved {
+                            outgoing.extend(game_message_update(game));
+                        }
+                        (chat_id.clone(), outgoing)
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            for (chat_id, outgoing) in outgoing {
+                if let Err(e) = bot::send_all(&api, outgoing).await {
+                    eprintln!("Vote timeout error for chat {}: {:?}", chat_id, e);
                 }
             }
+        }
+    });
+}
+
+/// Resolves the challenges whose voting time is out. Tells whether a proof was rejected,
+/// so that the game message needs an update.
+fn close_expired_challenges(chat_id: &str, game: &mut Game, outgoing: &mut Vec<Outgoing>) -> bool {
+    let vote_secs = game.settings.vote_hours as u64 * HOUR_SECS;
+    let now = now();
+    let expired = game
+        .proof_challenges
+        .iter()
+        .filter(|(_, challenge)| challenge.opened_at + vote_secs <= now)
+        .map(|(msg, _)| msg.clone())
+        .collect::<Vec<_>>();
+    if expired.is_empty() {
+        return false;
+    }
+    persistence::mark_chat_dirty(chat_id.to_owned());
+
+    close_challenges(game, expired, outgoing)
+}
 
-            dropbox::save_games(&games).await;
+/// Resolves the challenges of the proofs with the votes cast, as if their voting time
+/// was out. Without enough of them the proof stays. Tells whether a proof was rejected,
+/// so that the game message needs an update.
+pub(crate) fn close_challenges(
+    game: &mut Game,
+    proof_msgs: Vec<GameMessage>,
+    outgoing: &mut Vec<Outgoing>,
+) -> bool {
+    let mut should_update_game_message = false;
+    for msg in proof_msgs {
+        let challenge = match game.proof_challenges.shift_remove(&msg) {
+            Some(challenge) => challenge,
+            None => continue,
+        };
+
+        let tally = Tally {
+            num_yes: challenge.num_yes(),
+            num_no: challenge.num_no(),
+            num_allowed: num_allowed_voters(game, &challenge.user),
+            admin_vote: None,
+        };
+        let (is_accepted, verdict) = match voting::timeout_verdict(&game.settings, &tally) {
+            Some(is_accepted) => (
+                is_accepted,
+                format!("Время вышло: {}", verdict_caption(is_accepted)),
+            ),
+            None => (
+                true,
+                format!(
+                    "Время вышло, проголосовало слишком мало участников: {}",
+                    verdict_caption(true)
+                ),
+            ),
+        };
+
+        let tricks = format_tricks(game, &challenge.proof);
+        outgoing.push(Outgoing::ClosedPoll {
+            poll_msg: challenge.poll_msg.clone(),
+            text: format_verdict(&tricks, &challenge, &verdict),
+            parse_mode: Some(ParseMode::MarkdownV2),
+        });
+
+        if !is_accepted && reject_proof(game, &challenge.user, &challenge.proof, outgoing) {
+            should_update_game_message = true;
         }
     }
 
-    Ok(())
+    should_update_game_message
+}
+
+/// Decides the open challenges that the votes cast are enough for now that fewer may
+/// vote. Tells whether a proof was rejected, so that the game message needs an update.
+pub(crate) fn recount_votes(game: &mut Game, outgoing: &mut Vec<Outgoing>) -> bool {
+    let decided = game
+        .proof_challenges
+        .iter()
+        .filter_map(|(msg, challenge)| {
+            let tally = Tally {
+                num_yes: challenge.num_yes(),
+                num_no: challenge.num_no(),
+                num_allowed: num_allowed_voters(game, &challenge.user),
+                admin_vote: None,
+            };
+            voting::verdict(&game.settings, &tally).map(|is_accepted| (msg.clone(), is_accepted))
+        })
+        .collect::<Vec<_>>();
+
+    let mut should_update_game_message = false;
+    for (msg, is_accepted) in decided {
+        let challenge = match game.proof_challenges.shift_remove(&msg) {
+            Some(challenge) => challenge,
+            None => continue,
+        };
+
+        let tricks = format_tricks(game, &challenge.proof);
+        outgoing.push(Outgoing::ClosedPoll {
+            poll_msg: challenge.poll_msg.clone(),
+            text: format_verdict(&tricks, &challenge, verdict_caption(is_accepted)),
+            parse_mode: Some(ParseMode::MarkdownV2),
+        });
+
+        if !is_accepted && reject_proof(game, &challenge.user, &challenge.proof, outgoing) {
+            should_update_game_message = true;
+        }
+    }
+
+    should_update_game_message
+}
+
+/// Removes the proof rejected by the vote from the proofs of its owner. Tells whether
+/// it was there, so that the game message needs an update.
+fn reject_proof(
+    game: &mut Game,
+    owner: &GameUser,
+    proof: &Proof,
+    outgoing: &mut Vec<Outgoing>,
+) -> bool {
+    if game.remove_proof(owner, &proof.msg).is_none() {
+        return false;
+    }
+
+    outgoing.push(Outgoing::Reply {
+        msg: proof.msg.clone(),
+        text: "Это доказательство удалено.".to_owned(),
+    });
+
+    true
+}
+
+/// How many participants may vote on a proof of the owner.
+pub(crate) fn num_allowed_voters(game: &Game, owner: &GameUser) -> usize {
+    if !game.settings.owner_votes && game.participants.contains_key(owner) {
+        game.participants.len() - 1
+    } else {
+        game.participants.len()
+    }
 }
 
-pub(crate) async fn challenge_proof(
+fn format_tricks(game: &Game, proof: &Proof) -> String {
+    proof
+        .tricks_proven
+        .iter()
+        .flat_map(|trick_id| game.trick_by_id(*trick_id))
+        .map(|trick| format!("\"{}\"", trick.name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn format_voters(challenge: &ProofChallenge) -> String {
+    challenge
+        .votes
+        .keys()
+        .map(|voter| format!("[{}](tg://user?id={})", voter.first_name, voter.id))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn verdict_caption(is_accepted: bool) -> &'static str {
+    if is_accepted {
+        "✅ ПРИНЯТО"
+    } else {
+        "❌ ПЕРЕДЕЛАТЬ"
+    }
+}
+
+/// Text of the poll once the vote is over.
+fn format_verdict(tricks: &str, challenge: &ProofChallenge, verdict: &str) -> String {
+    format!(
+        "На этом видео выполнены эти трюки: {}?\n\nВердикт:*{}*\n\n_Проголосовали: {}_\n\n{} 👍, {} 👎",
+        tricks,
+        verdict,
+        format_voters(challenge),
+        challenge.num_yes(),
+        challenge.num_no(),
+    )
+}
+
+pub(crate) async fn challenge_proof<B: Bot>(
     game: &mut Game,
-    api: &mut Api,
+    api: &mut B,
     message: &Message,
     user: &GameUser,
     participant: Participant,
     proof: Proof,
 ) -> Result<(), Error> {
-    let tricks = proof
-        .tricks_proven
-        .iter()
-        .flat_map(|trick_no| game.trick_by_number(*trick_no))
-        .map(|trick| format!("\"{}\"", trick.name))
-        .collect::<Vec<_>>()
-        .join(", ");
+    let tricks = format_tricks(game, &proof);
     let mut msg = message.text_reply(format!("На этом видео выполнены эти трюки: {}?", tricks));
 
     let inline_keyboard = build_poll_keyboard(
+        "",
         i64::from(message.chat.id()),
         user.id,
         proof.msg.id,
@@ -205,24 +443,29 @@ pub(crate) async fn challenge_proof(
     let msg = msg.reply_markup(inline_keyboard);
 
     if let MessageOrChannelPost::Message(msg) = api.send(msg).await? {
-        game.proof_challenge = Some(ProofChallenge {
-            user: user.clone(),
-            participant,
-            proof,
-            poll_msg: msg.into(),
-            num_yes: 0,
-            num_no: 0,
-            voters: Default::default(),
-        });
+        let opened_at = msg.date as u64;
+        game.proof_challenges.insert(
+            proof.msg.clone(),
+            ProofChallenge {
+                user: user.clone(),
+                participant,
+                proof,
+                poll_msg: msg.into(),
+                votes: Default::default(),
+                opened_at,
+            },
+        );
     }
 
     Ok(())
 }
 
-fn build_poll_keyboard(
+/// Yes/no keyboard of a vote, the callback data of its buttons starts with `prefix`.
+pub(crate) fn build_poll_keyboard(
+    prefix: &str,
     chat_id: i64,
     user_id: i64,
-    proof_msg_id: i64,
+    msg_id: i64,
     num_yes: Option<usize>,
     num_no: Option<usize>,
 ) -> InlineKeyboardMarkup {
@@ -234,7 +477,7 @@ fn build_poll_keyboard(
             "".to_owned()
         }
     );
-    let yes_button_data = format!("yes,{},{},{}", chat_id, user_id, proof_msg_id);
+    let yes_button_data = format!("{}yes,{},{},{}", prefix, chat_id, user_id, msg_id);
 
     let no_button_caption = format!(
         "👎 Нет{}",
@@ -244,7 +487,7 @@ fn build_poll_keyboard(
             "".to_owned()
         }
     );
-    let no_button_data = format!("no,{},{},{}", chat_id, user_id, proof_msg_id);
+    let no_button_data = format!("{}no,{},{},{}", prefix, chat_id, user_id, msg_id);
 
     reply_markup!(inline_keyboard,
         [yes_button_caption callback yes_button_data, no_button_caption callback no_button_data]
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{NaiveDate, NaiveDateTime, TimeZone, Utc};
+use telegram_bot::*;
+
+use crate::bot::{self, Bot};
+use crate::commands::challenge;
+use crate::commands::lifecycle::{finish_game, FINISHED_REPLY};
+use crate::time::{now, DAY_SECS, HOUR_SECS};
+use crate::types::*;
+use crate::{persistence, update_game_message, GAMES};
+
+/// How long before the deadline reminders are posted, from the earliest one.
+const REMINDERS: &[(u64, &str)] = &[
+    (7 * DAY_SECS, "7 дней"),
+    (DAY_SECS, "1 день"),
+    (HOUR_SECS, "1 час"),
+];
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+pub(crate) fn format_deadline(deadline: u64) -> String {
+    Utc.timestamp(deadline as i64, 0)
+        .format("%d.%m.%Y %H:%M UTC")
+        .to_string()
+}
+
+/// Parses `дд.мм.гггг чч:мм` in UTC, or just the date meaning the end of that day.
+fn parse_deadline(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let time = NaiveDateTime::parse_from_str(s, "%d.%m.%Y %H:%M")
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(s, "%d.%m.%Y")
+                .ok()
+                .map(|date| date.and_hms(23, 59, 0))
+        })?;
+
+    Some(Utc.from_utc_datetime(&time).timestamp() as u64)
+}
+
+/// Sets the time the game closes at. Reminders for the time that's already past are
+/// skipped, so that a deadline set three days ahead won't warn about a week left.
+fn set_deadline(game: &mut Game, deadline: Option<u64>) {
+    game.deadline = deadline;
+    game.last_reminder = deadline.and_then(|deadline| {
+        let left = deadline.saturating_sub(now());
+        REMINDERS
+            .iter()
+            .map(|(offset, _)| *offset)
+            .filter(|offset| *offset >= left)
+            .min()
+    });
+}
+
+pub(crate) async fn process_deadline_command<B: Bot>(
+    games: &mut HashMap<String, Game>,
+    api: &mut B,
+    message: &Message,
+    rest: &str,
+) -> Result<(), Error> {
+    let game = games
+        .entry(message.chat.id().to_string())
+        .or_insert(Default::default());
+
+    if game.finished() {
+        api.send(message.text_reply(FINISHED_REPLY)).await?;
+        return Ok(());
+    }
+
+    let reply = match rest.trim() {
+        "" => match game.deadline {
+            Some(deadline) => format!(
+                "Игра закроется {}. Убрать срок: /deadline off",
+                format_deadline(deadline)
+            ),
+            None => {
+                "Срок у игры не задан. Задать: /deadline дд.мм.гггг [чч:мм] (время UTC)".to_owned()
+            }
+        },
+
+        "off" => {
+            set_deadline(game, None);
+            "Срок игры убран.".to_owned()
+        }
+
+        rest => match parse_deadline(rest) {
+            Some(deadline) if deadline > now() => {
+                set_deadline(game, Some(deadline));
+                format!("Игра закроется {}.", format_deadline(deadline))
+            }
+            Some(_) => "Этот срок уже прошел.".to_owned(),
+            None => "Не удалось разобрать срок. Формат: дд.мм.гггг [чч:мм] (время UTC)".to_owned(),
+        },
+    };
+
+    if game.started() {
+        update_game_message(api, &message.chat, game).await?;
+    }
+    persistence::mark_dirty(&message.chat);
+
+    api.send(message.text_reply(reply)).await?;
+
+    Ok(())
+}
+
+/// Starts the background task that reminds the chats about their deadlines and closes
+/// the games once those pass. The reminders sent are saved with the games, so nothing
+/// is repeated after a restart.
+pub(crate) fn spawn<B: Bot>(api: B) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::delay_for(CHECK_INTERVAL).await;
+
+            let mut games = GAMES.lock().await;
+            for (chat_id, game) in games.iter_mut() {
+                if let Err(e) = check_deadline(&api, chat_id, game).await {
+                    eprintln!("Deadline check error for chat {}: {:?}", chat_id, e);
+                }
+            }
+        }
+    });
+}
+
+pub(crate) async fn check_deadline<B: Bot>(
+    api: &B,
+    chat_id: &str,
+    game: &mut Game,
+) -> Result<(), Error> {
+    let deadline = match game.deadline {
+        Some(deadline) if !game.finished() => deadline,
+        _ => return Ok(()),
+    };
+    let chat = match chat_id.parse::<i64>() {
+        Ok(id) => ChatId::new(id),
+        Err(_) => return Ok(()),
+    };
+
+    let left = deadline.saturating_sub(now());
+    if left == 0 {
+        game.deadline = None;
+        persistence::mark_chat_dirty(chat_id.to_owned());
+
+        if game.started() {
+            // Votes still open are decided by what was cast, before the results are taken
+            let open = game.proof_challenges.keys().cloned().collect();
+            let mut outgoing = vec![];
+            challenge::close_challenges(game, open, &mut outgoing);
+            bot::send_all(api, outgoing).await?;
+            finish_game(&mut api.clone(), &chat, game).await?;
+        }
+        return Ok(());
+    }
+
+    // The latest reminder due, the one with the least time left
+    let due = REMINDERS.iter().rev().find(|(offset, _)| *offset >= left);
+    if let Some((offset, time_left)) = due {
+        let already_sent = game.last_reminder.filter(|last| last <= offset).is_some();
+        if !already_sent {
+            game.last_reminder = Some(*offset);
+            persistence::mark_chat_dirty(chat_id.to_owned());
+
+            if game.started() {
+                api.send(chat.text(format!(
+                    "⏰ До конца игры осталось {} (срок: {}).",
+                    time_left,
+                    format_deadline(deadline)
+                )))
+                .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
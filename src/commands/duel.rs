@@ -0,0 +1,685 @@
+use std::collections::HashMap;
+
+use telegram_bot::*;
+
+use crate::bot::{self, Bot, Outgoing};
+use crate::commands::challenge::build_poll_keyboard;
+use crate::time::{now, HOUR_SECS};
+use crate::types::*;
+use crate::voting::{self, Tally, VotingPolicy};
+use crate::{escape_markdown, is_admin, persistence, update_game_message};
+
+/// Callback data of the dispute votes starts with this.
+pub(crate) const CALLBACK_PREFIX: &str = "duel,";
+
+const USAGE: &str = "Дуэль S.K.A.T.E.:\n\
+    /duel @username - вызвать на дуэль (или /duel в ответе на сообщение соперника)\n\
+    /duel set <название> - поставить трюк, в подписи к видео или в ответе на него\n\
+    /duel land - повторить поставленный трюк, в подписи к видео или в ответе на него\n\
+    /duel miss - не удалось повторить трюк, взять букву\n\
+    /duel pass - не удалось поставить трюк, передать ход\n\
+    /duel stop - остановить дуэль\n\
+    /challenge - в ответе на видео повтора, чтобы оспорить его";
+
+fn player_name(user: &GameUser) -> String {
+    match &user.username {
+        Some(username) => format!("{} @{}", user.first_name, username),
+        None => user.first_name.clone(),
+    }
+}
+
+fn format_letters(player: &DuelPlayer) -> String {
+    if player.letters == 0 {
+        "—".to_owned()
+    } else {
+        player.letters()
+    }
+}
+
+/// Section of the pinned message with the letters of both players.
+pub(crate) fn format_duel(duel: &Duel) -> String {
+    let players = duel
+        .players
+        .iter()
+        .map(|player| {
+            format!(
+                "{} — {}",
+                escape_markdown(&player_name(&player.user)),
+                format_letters(player)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let setter = &duel.players[duel.setter].user;
+    let responder = &duel.players[duel.responder()].user;
+    let turn = match (duel.winner(), &duel.set_trick, &duel.dispute) {
+        (Some(winner), _, _) => format!("🏆 Победитель: {}", winner.user.first_name),
+        (None, Some(trick), _) => format!("Повторяет «{}»: {}", trick.name, responder.first_name),
+        (None, None, Some(_)) => "Идет голосование по повтору".to_owned(),
+        (None, None, None) => format!("Ставит трюк: {}", setter.first_name),
+    };
+
+    format!(
+        "=== Дуэль S.K.A.T.E. ===\n\n{}\n\n{}",
+        players,
+        escape_markdown(&turn)
+    )
+}
+
+fn running_duel(game: &mut Game) -> Option<&mut Duel> {
+    game.duel.as_mut().filter(|duel| duel.winner().is_none())
+}
+
+/// Finds who is called out: a mentioned user, a user known in the chat by username,
+/// or the author of the replied message.
+fn find_opponent(game: &Game, message: &Message, rest: &str) -> Result<GameUser, String> {
+    if let MessageKind::Text { entities, .. } = &message.kind {
+        for entity in entities {
+            if let MessageEntityKind::TextMention(user) = &entity.kind {
+                return Ok(user.clone().into());
+            }
+        }
+    }
+
+    if rest.starts_with('@') {
+        let username = rest.trim_start_matches('@');
+        return game
+            .participants
+            .keys()
+            .chain(
+                game.history
+                    .iter()
+                    .flat_map(|finished| finished.participants.keys()),
+            )
+            .chain(
+                game.duel
+                    .iter()
+                    .flat_map(|duel| duel.players.iter().map(|player| &player.user)),
+            )
+            .find(|user| {
+                user.username
+                    .as_deref()
+                    .map_or(false, |name| name.eq_ignore_ascii_case(username))
+            })
+            .cloned()
+            .ok_or_else(|| {
+                format!(
+                    "Не знаю @{}. Вызови соперника ответом на его сообщение: /duel",
+                    username
+                )
+            });
+    }
+
+    match message.reply_to_message.as_deref() {
+        Some(MessageOrChannelPost::Message(reply)) if !reply.from.is_bot => {
+            Ok(reply.from.clone().into())
+        }
+        _ => Err(USAGE.to_owned()),
+    }
+}
+
+/// Handles `/duel` and its subcommands. `video` is the video the command was sent with,
+/// either as its caption or in reply to it.
+pub(crate) async fn process_duel_command<B: Bot>(
+    games: &mut HashMap<String, Game>,
+    api: &mut B,
+    message: &Message,
+    rest: &str,
+    video: Option<&Message>,
+) -> Result<(), Error> {
+    let game = games
+        .entry(message.chat.id().to_string())
+        .or_insert(Default::default());
+
+    let rest = rest.trim();
+    let (subcommand, arg) = match rest.find(' ') {
+        Some(pos) => (&rest[..pos], rest[pos..].trim()),
+        None => (rest, ""),
+    };
+    let sender: GameUser = message.from.clone().into();
+    let mut changed = false;
+
+    let reply = match subcommand.to_lowercase().as_str() {
+        "set" | "land" | "miss" | "pass" | "stop" if running_duel(game).is_none() => {
+            "Дуэль сейчас не идет. Вызвать соперника: /duel @username".to_owned()
+        }
+
+        "set" => {
+            let duel = running_duel(game).unwrap();
+            let responder = duel.players[duel.responder()].user.clone();
+            if duel.players[duel.setter].user != sender {
+                format!(
+                    "Сейчас трюк ставит {}.",
+                    duel.players[duel.setter].user.first_name
+                )
+            } else if let Some(trick) = &duel.set_trick {
+                format!(
+                    "Сначала {} должен повторить «{}».",
+                    responder.first_name, trick.name
+                )
+            } else if duel.dispute.is_some() {
+                "Идет голосование по повтору, нужно дождаться его завершения.".to_owned()
+            } else if arg.is_empty() {
+                "Название трюка не указано!".to_owned()
+            } else {
+                match video {
+                    Some(video) => {
+                        duel.set_trick = Some(DuelTrick {
+                            name: arg.to_owned(),
+                            msg: video.clone().into(),
+                        });
+                        duel.landed_trick = None;
+                        changed = true;
+                        format!(
+                            "🛹 {} ставит «{}». {}, повтори трюк: /duel land в ответе на видео, \
+                            или /duel miss, если не выходит.",
+                            sender.first_name,
+                            arg,
+                            player_name(&responder)
+                        )
+                    }
+                    None => "Трюк ставится видео: /duel set <название> в подписи к видео \
+                        или в ответе на него."
+                        .to_owned(),
+                }
+            }
+        }
+
+        "land" => {
+            let duel = running_duel(game).unwrap();
+            let setter = duel.players[duel.setter].user.clone();
+            match (&duel.set_trick, video) {
+                _ if duel.players[duel.responder()].user != sender => {
+                    format!("Сейчас трюк ставит {}.", setter.first_name)
+                }
+                (None, _) => format!("{} еще не поставил трюк.", setter.first_name),
+                (Some(trick), Some(video)) if trick.msg != GameMessage::from(video.clone()) => {
+                    let name = trick.name.clone();
+                    duel.landed_trick = Some(DuelTrick {
+                        name: name.clone(),
+                        msg: video.clone().into(),
+                    });
+                    duel.set_trick = None;
+                    changed = true;
+                    format!(
+                        "✅ {} повторяет «{}»! {} ставит следующий трюк. \
+                        Если повтор не чистый, его можно оспорить: /challenge в ответе на видео.",
+                        sender.first_name,
+                        name,
+                        player_name(&setter)
+                    )
+                }
+                (Some(_), _) => "Повтор засчитывается видео: /duel land в подписи к видео \
+                    или в ответе на него."
+                    .to_owned(),
+            }
+        }
+
+        "miss" => {
+            let duel = running_duel(game).unwrap();
+            let responder = duel.responder();
+            if duel.players[responder].user != sender {
+                format!(
+                    "Сейчас трюк ставит {}.",
+                    duel.players[duel.setter].user.first_name
+                )
+            } else if duel.set_trick.is_none() {
+                format!(
+                    "{} еще не поставил трюк.",
+                    duel.players[duel.setter].user.first_name
+                )
+            } else {
+                duel.set_trick = None;
+                duel.give_letter(responder);
+                changed = true;
+                format_letter_result(duel, responder)
+            }
+        }
+
+        "pass" => {
+            let duel = running_duel(game).unwrap();
+            if duel.players[duel.setter].user != sender {
+                format!(
+                    "Сейчас трюк ставит {}.",
+                    duel.players[duel.setter].user.first_name
+                )
+            } else if duel.set_trick.is_some() || duel.dispute.is_some() {
+                "Трюк уже поставлен, передать ход можно перед следующим.".to_owned()
+            } else {
+                duel.switch_setter();
+                changed = true;
+                format!(
+                    "{} не смог поставить трюк. Теперь трюки ставит {}.",
+                    sender.first_name,
+                    player_name(&duel.players[duel.setter].user)
+                )
+            }
+        }
+
+        "stop" => {
+            let duel = running_duel(game).unwrap();
+            if duel.player_index(&sender).is_none() && !is_admin(&message.from) {
+                "Остановить дуэль могут только ее участники.".to_owned()
+            } else {
+                game.duel = None;
+                changed = true;
+                "Дуэль остановлена.".to_owned()
+            }
+        }
+
+        "" if message.reply_to_message.is_none() => match running_duel(game) {
+            Some(duel) => format!("{}\n\n{}", format_duel_status(duel), USAGE),
+            None => USAGE.to_owned(),
+        },
+
+        _ => {
+            if let Some(duel) = running_duel(game) {
+                format!(
+                    "Уже идет дуэль: {} против {}. Остановить ее: /duel stop",
+                    duel.players[0].user.first_name, duel.players[1].user.first_name
+                )
+            } else {
+                match find_opponent(game, message, rest) {
+                    Ok(opponent) if opponent == sender => {
+                        "Вызвать на дуэль самого себя не получится.".to_owned()
+                    }
+                    Ok(opponent) => {
+                        let reply = format!(
+                            "⚔️ {} вызывает на дуэль S.K.A.T.E. {}! {} ставит первый трюк: \
+                            /duel set <название> в подписи к видео.",
+                            sender.first_name,
+                            player_name(&opponent),
+                            sender.first_name
+                        );
+                        game.duel = Some(Duel::new(sender.clone(), opponent));
+                        changed = true;
+                        reply
+                    }
+                    Err(reply) => {
+                        api.send(message.text_reply(reply)).await?;
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    };
+
+    api.send(message.text_reply(reply)).await?;
+
+    if changed {
+        update_game_message(api, &message.chat, game).await?;
+        persistence::mark_dirty(&message.chat);
+    }
+
+    Ok(())
+}
+
+fn format_duel_status(duel: &Duel) -> String {
+    duel.players
+        .iter()
+        .map(|player| format!("{} — {}", player_name(&player.user), format_letters(player)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Describes the letter the player just got, and the end of the duel if it was the last one.
+fn format_letter_result(duel: &Duel, index: usize) -> String {
+    let player = &duel.players[index];
+    match duel.winner() {
+        Some(winner) => format!(
+            "❌ {} собирает S.K.A.T.E.\n\n🏆 {} побеждает в дуэли!",
+            player.user.first_name,
+            player_name(&winner.user)
+        ),
+        None => format!(
+            "❌ {} получает букву: {}. Трюк ставит {}.",
+            player.user.first_name,
+            player.letters(),
+            duel.players[duel.setter].user.first_name
+        ),
+    }
+}
+
+/// Whether the message is the latest repeat in the duel of the chat, which can be disputed.
+pub(crate) fn is_landed_trick(game: &Game, msg: &GameMessage) -> bool {
+    game.duel
+        .as_ref()
+        .and_then(|duel| duel.landed_trick.as_ref())
+        .map_or(false, |trick| trick.msg == *msg)
+}
+
+/// Starts a vote on whether the trick was really repeated in the video.
+pub(crate) async fn dispute_landing<B: Bot>(
+    game: &mut Game,
+    api: &mut B,
+    message: &Message,
+    video: &Message,
+) -> Result<(), Error> {
+    let duel = match running_duel(game) {
+        Some(duel) => duel,
+        None => return Ok(()),
+    };
+    let responder = duel.players[duel.responder()].user.clone();
+    let trick = match &duel.landed_trick {
+        Some(trick) => trick.clone(),
+        None => return Ok(()),
+    };
+
+    if duel.dispute.is_some() {
+        api.send(message.text_reply("Голосование по этому повтору уже идет."))
+            .await?;
+        return Ok(());
+    }
+
+    if GameUser::from(message.from.clone()) == responder {
+        api.send(message.text_reply("Оспаривать свой повтор нельзя."))
+            .await?;
+        return Ok(());
+    }
+
+    let keyboard = build_poll_keyboard(
+        CALLBACK_PREFIX,
+        i64::from(message.chat.id()),
+        responder.id,
+        trick.msg.id,
+        None,
+        None,
+    );
+    let mut poll = video.text_reply(format!(
+        "{} чисто повторил «{}»?",
+        responder.first_name, trick.name
+    ));
+    let poll = poll.reply_markup(keyboard);
+
+    if let MessageOrChannelPost::Message(poll_msg) = api.send(poll).await? {
+        let opened_at = poll_msg.date as u64;
+        duel.dispute = Some(DuelDispute {
+            poll_msg: poll_msg.into(),
+            votes: Default::default(),
+            opened_at,
+        });
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn process_callback_query<B: Bot>(
+    games: &mut HashMap<String, Game>,
+    api: B,
+    cb: CallbackQuery,
+) -> Result<(), Error> {
+    let message = match &cb.message {
+        Some(MessageOrChannelPost::Message(message)) => message,
+        _ => return Ok(()),
+    };
+    let data = cb.data.as_deref().unwrap_or("");
+    let data = data.split(',').collect::<Vec<_>>();
+    if data.len() < 2 {
+        return Ok(());
+    }
+
+    let game = games
+        .entry(message.chat.id().to_string())
+        .or_insert(Default::default());
+    let poll_msg = GameMessage::from(message.clone());
+    let duel = match &game.duel {
+        Some(duel)
+            if duel.winner().is_none()
+                && duel.dispute.as_ref().map(|dispute| &dispute.poll_msg) == Some(&poll_msg) =>
+        {
+            duel
+        }
+        _ => {
+            api.send(cb.answer("Голосование уже завершено.")).await?;
+            return Ok(());
+        }
+    };
+
+    let user: GameUser = cb.from.clone().into();
+    let responder = duel.players[duel.responder()].user.clone();
+    if duel.player_index(&user).is_some() {
+        api.send(cb.answer("Игроки дуэли за повтор не голосуют."))
+            .await?;
+        return Ok(());
+    }
+
+    // With the veto an admin decides even without playing
+    let is_veto = game.settings.voting == VotingPolicy::AdminVeto && is_admin(&cb.from);
+    let is_voter = is_voter(game, duel, &user);
+    if !is_voter && !is_veto {
+        api.send(cb.answer("Голосовать могут только участники игры."))
+            .await?;
+        return Ok(());
+    }
+    let num_allowed = num_allowed_voters(game, duel);
+
+    let vote = match data[1] {
+        "yes" => true,
+        "no" => false,
+        _ => return Ok(()),
+    };
+
+    let settings = game.settings.clone();
+    let dispute = match game.duel.as_mut().and_then(|duel| duel.dispute.as_mut()) {
+        Some(dispute) => dispute,
+        None => return Ok(()),
+    };
+
+    // Pressing the same button again takes the vote back. The vote of an admin outside
+    // the game decides at once and isn't one of the counted ones.
+    let answer = match dispute.votes.get(&user) {
+        _ if !is_voter => "Твой голос принят.",
+        Some(&previous) if previous == vote => {
+            dispute.votes.shift_remove(&user);
+            "Твой голос отменен."
+        }
+        Some(_) => {
+            dispute.votes.insert(user.clone(), vote);
+            "Твой голос изменен."
+        }
+        None => {
+            dispute.votes.insert(user.clone(), vote);
+            "Твой голос принят."
+        }
+    };
+    api.send(cb.answer(answer)).await?;
+
+    let tally = Tally {
+        num_yes: dispute.num_yes(),
+        num_no: dispute.num_no(),
+        num_allowed,
+        admin_vote: match (is_veto, is_voter) {
+            (true, true) => dispute.votes.get(&user).copied(),
+            (true, false) => Some(vote),
+            (false, _) => None,
+        },
+    };
+    match voting::verdict(&settings, &tally) {
+        Some(is_landed) => {
+            resolve_dispute(
+                &api,
+                message.chat.id(),
+                game,
+                is_landed,
+                verdict_caption(is_landed),
+            )
+            .await?
+        }
+        None => {
+            let keyboard = build_poll_keyboard(
+                CALLBACK_PREFIX,
+                dispute.poll_msg.chat_id,
+                responder.id,
+                dispute.poll_msg.id,
+                Some(dispute.num_yes()),
+                Some(dispute.num_no()),
+            );
+            api.send(message.edit_reply_markup(Some(keyboard))).await?;
+        }
+    }
+
+    persistence::mark_dirty(&message.chat);
+
+    Ok(())
+}
+
+/// Resolves the vote on the repeat in the duel of the chat once its voting time is out.
+/// Without enough votes the repeat counts. Tells whether the vote was resolved, so that
+/// the game message needs an update.
+pub(crate) fn close_expired_dispute(
+    chat_id: &str,
+    game: &mut Game,
+    outgoing: &mut Vec<Outgoing>,
+) -> bool {
+    let vote_secs = game.settings.vote_hours as u64 * HOUR_SECS;
+    let duel = match game.duel.as_ref().filter(|duel| duel.winner().is_none()) {
+        Some(duel) => duel,
+        None => return false,
+    };
+    let dispute = match &duel.dispute {
+        Some(dispute) if dispute.opened_at + vote_secs <= now() => dispute,
+        _ => return false,
+    };
+    let chat = match chat_id.parse::<i64>() {
+        Ok(id) => ChatId::new(id),
+        Err(_) => return false,
+    };
+    persistence::mark_chat_dirty(chat_id.to_owned());
+
+    let tally = Tally {
+        num_yes: dispute.num_yes(),
+        num_no: dispute.num_no(),
+        num_allowed: num_allowed_voters(game, duel),
+        admin_vote: None,
+    };
+    let (is_landed, verdict) = match voting::timeout_verdict(&game.settings, &tally) {
+        Some(is_landed) => (
+            is_landed,
+            format!("Время вышло: {}", verdict_caption(is_landed)),
+        ),
+        None => (
+            true,
+            format!(
+                "Время вышло, проголосовало слишком мало участников: {}",
+                verdict_caption(true)
+            ),
+        ),
+    };
+
+    settle_dispute(chat, game, is_landed, &verdict, outgoing)
+}
+
+/// Decides the vote on the repeat if the votes cast are enough for it now that fewer may
+/// vote. Tells whether it was decided, so that the game message needs an update.
+pub(crate) fn recount_dispute(chat: ChatId, game: &mut Game, outgoing: &mut Vec<Outgoing>) -> bool {
+    let duel = match game.duel.as_ref().filter(|duel| duel.winner().is_none()) {
+        Some(duel) => duel,
+        None => return false,
+    };
+    let dispute = match &duel.dispute {
+        Some(dispute) => dispute,
+        None => return false,
+    };
+
+    let tally = Tally {
+        num_yes: dispute.num_yes(),
+        num_no: dispute.num_no(),
+        num_allowed: num_allowed_voters(game, duel),
+        admin_vote: None,
+    };
+    match voting::verdict(&game.settings, &tally) {
+        Some(is_landed) => {
+            settle_dispute(chat, game, is_landed, verdict_caption(is_landed), outgoing)
+        }
+        None => false,
+    }
+}
+
+/// Whether the user may vote on a repeat in the duel: a participant of the game, but
+/// not a player of the duel, since both have a stake in the verdict.
+fn is_voter(game: &Game, duel: &Duel, user: &GameUser) -> bool {
+    game.participants.contains_key(user) && duel.player_index(user).is_none()
+}
+
+/// How many may vote on a repeat in the duel.
+fn num_allowed_voters(game: &Game, duel: &Duel) -> usize {
+    game.participants
+        .keys()
+        .filter(|user| is_voter(game, duel, user))
+        .count()
+}
+
+fn verdict_caption(is_landed: bool) -> &'static str {
+    if is_landed {
+        "✅ ЗАСЧИТАНО"
+    } else {
+        "❌ НЕ ЗАСЧИТАНО"
+    }
+}
+
+/// Closes the vote on the repeat with the verdict. If the repeat doesn't count,
+/// the player who did it gets a letter.
+async fn resolve_dispute<B: Bot>(
+    api: &B,
+    chat: ChatId,
+    game: &mut Game,
+    is_landed: bool,
+    verdict: &str,
+) -> Result<(), Error> {
+    let mut outgoing = vec![];
+    if settle_dispute(chat, game, is_landed, verdict, &mut outgoing) {
+        bot::send_all(api, outgoing).await?;
+        update_game_message(&mut api.clone(), &chat, game).await?;
+    }
+    Ok(())
+}
+
+/// Closes the vote like [`resolve_dispute`], leaving the messages to send. Tells whether
+/// there was a vote to close.
+fn settle_dispute(
+    chat: ChatId,
+    game: &mut Game,
+    is_landed: bool,
+    verdict: &str,
+    outgoing: &mut Vec<Outgoing>,
+) -> bool {
+    let duel = match game.duel.as_mut() {
+        Some(duel) => duel,
+        None => return false,
+    };
+    let dispute = match duel.dispute.take() {
+        Some(dispute) => dispute,
+        None => return false,
+    };
+    let responder = duel.responder();
+    let trick_name = duel
+        .landed_trick
+        .take()
+        .map(|trick| trick.name)
+        .unwrap_or_default();
+
+    let text = format!(
+        "{} чисто повторил «{}»?\n\nВердикт: {}\n\n{} 👍, {} 👎",
+        duel.players[responder].user.first_name,
+        trick_name,
+        verdict,
+        dispute.num_yes(),
+        dispute.num_no()
+    );
+    outgoing.push(Outgoing::ClosedPoll {
+        poll_msg: dispute.poll_msg,
+        text,
+        parse_mode: None,
+    });
+
+    if !is_landed {
+        duel.give_letter(responder);
+        outgoing.push(Outgoing::Text {
+            chat,
+            text: format_letter_result(duel, responder),
+        });
+    }
+
+    true
+}
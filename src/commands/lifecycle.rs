@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use chrono::{TimeZone, Utc};
+use telegram_bot::*;
+
+use crate::bot::Bot;
+use crate::time::now;
+use crate::types::*;
+use crate::{persistence, update_game_message};
+
+/// How many of the latest finished games `/history` lists.
+const MAX_LISTED_GAMES: usize = 10;
+
+pub(crate) const FINISHED_REPLY: &str = "Игра завершена. Новую можно начать командой /newgame.";
+
+fn format_date(timestamp: u64) -> String {
+    Utc.timestamp(timestamp as i64, 0)
+        .format("%d.%m.%Y")
+        .to_string()
+}
+
+fn format_standings(game: &Game) -> String {
+    game.standings()
+        .into_iter()
+        .map(|(place, user, points)| {
+            format!(
+                "{place} {firstname}{name} — очков: {points}",
+                place = match place {
+                    1 => "🥇".to_owned(),
+                    2 => "🥈".to_owned(),
+                    3 => "🥉".to_owned(),
+                    _ => format!("{}.", place),
+                },
+                firstname = user.first_name,
+                name = user
+                    .username
+                    .map(|username| format!(" @{}", username))
+                    .unwrap_or_default(),
+                points = points,
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub(crate) async fn process_endgame_command<B: Bot>(
+    games: &mut HashMap<String, Game>,
+    api: &mut B,
+    message: &Message,
+) -> Result<(), Error> {
+    let game = games
+        .entry(message.chat.id().to_string())
+        .or_insert(Default::default());
+
+    if !game.started() {
+        api.send(message.text_reply("Игра еще не началась, завершать нечего."))
+            .await?;
+        return Ok(());
+    }
+
+    if game.finished() {
+        api.send(message.text_reply(FINISHED_REPLY)).await?;
+        return Ok(());
+    }
+
+    if !game.proof_challenges.is_empty() {
+        api.send(
+            message
+                .text_reply("Идут голосования по доказательствам, нужно дождаться их завершения."),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    finish_game(api, &message.chat, game).await?;
+    persistence::mark_dirty(&message.chat);
+
+    Ok(())
+}
+
+/// Freezes the game and posts the final standings to the chat.
+pub(crate) async fn finish_game<B: Bot, C: ToChatRef>(
+    api: &mut B,
+    chat: &C,
+    game: &mut Game,
+) -> Result<(), Error> {
+    game.finished_at = Some(now());
+    update_game_message(api, chat, game).await?;
+
+    api.send(chat.text(format!(
+        "🏁 Игра завершена!\n\nИтоги:\n{}\n\nНачать новую игру: /newgame",
+        format_standings(game)
+    )))
+    .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn process_newgame_command<B: Bot>(
+    games: &mut HashMap<String, Game>,
+    api: &mut B,
+    message: &Message,
+) -> Result<(), Error> {
+    let game = games
+        .entry(message.chat.id().to_string())
+        .or_insert(Default::default());
+
+    if !game.started() {
+        api.send(message.text_reply(
+            "Новая игра уже ждет участников! Добавь свой трюк через команду /trick <название>.",
+        ))
+        .await?;
+        return Ok(());
+    }
+
+    if !game.finished() {
+        api.send(
+            message
+                .text_reply("Текущая игра еще не завершена. Завершить ее можно командой /endgame."),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    game.start_new_round();
+    persistence::mark_dirty(&message.chat);
+
+    api.send(message.text_reply(
+        "Новая игра начата, прошлая сохранена в /history. \
+        Добавь свой трюк через команду /trick <название>.",
+    ))
+    .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn process_history_command<B: Bot>(
+    games: &mut HashMap<String, Game>,
+    api: &mut B,
+    message: &Message,
+) -> Result<(), Error> {
+    let game = games
+        .entry(message.chat.id().to_string())
+        .or_insert(Default::default());
+
+    // The current game counts once it's finished
+    let finished_games = game
+        .history
+        .iter()
+        .chain(Some(&*game).filter(|game| game.finished()))
+        .collect::<Vec<_>>();
+
+    if finished_games.is_empty() {
+        api.send(message.text_reply("Завершенных игр в этом чате пока нет."))
+            .await?;
+        return Ok(());
+    }
+
+    let history = finished_games
+        .iter()
+        .enumerate()
+        .rev()
+        .take(MAX_LISTED_GAMES)
+        .map(|(i, finished)| {
+            format!(
+                "Игра №{} ({}):\n{}",
+                i + 1,
+                finished.finished_at.map(format_date).unwrap_or_default(),
+                format_standings(finished)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    api.send(message.text_reply(history)).await?;
+
+    Ok(())
+}
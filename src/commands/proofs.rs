@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use telegram_bot::*;
+
+use crate::bot::Bot;
+use crate::commands::lifecycle::FINISHED_REPLY;
+use crate::types::*;
+use crate::{is_proof_media, persistence, update_game_message};
+
+const CHALLENGED_REPLY: &str = "По этому пруфу идет голосование, нужно дождаться его завершения.";
+
+fn is_challenged(game: &Game, msg: &GameMessage) -> bool {
+    game.proof_challenges.contains_key(msg)
+}
+
+pub(crate) async fn process_unproof_command<B: Bot>(
+    games: &mut HashMap<String, Game>,
+    api: &mut B,
+    message: &Message,
+) -> Result<(), Error> {
+    let game = games
+        .entry(message.chat.id().to_string())
+        .or_insert(Default::default());
+
+    if game.finished() {
+        api.send(message.text_reply(FINISHED_REPLY)).await?;
+        return Ok(());
+    }
+
+    let msg: GameMessage = match message.reply_to_message.as_deref() {
+        Some(MessageOrChannelPost::Message(reply)) => reply.clone().into(),
+        _ => {
+            api.send(
+                message.text_reply("Команду нужно отправить в ответ на видео-доказательство."),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+
+    let sender: GameUser = message.from.clone().into();
+    let reply = match game.find_participant_and_proof_by_msg(&msg) {
+        None => "Это сообщение не представляет собою доказательство трюка.",
+        Some((user, _, _)) if user != sender => "Убирать можно только свои пруфы.",
+        Some(_) if is_challenged(game, &msg) => CHALLENGED_REPLY,
+        Some(_) => {
+            game.remove_proof(&sender, &msg);
+            update_game_message(api, &message.chat, game).await?;
+            persistence::mark_dirty(&message.chat);
+
+            "Пруф убран из закрепленного поста."
+        }
+    };
+
+    api.send(message.text_reply(reply)).await?;
+
+    Ok(())
+}
+
+/// Replaces the sender's proof of the same tricks with a new video. `video` is the
+/// message the command was sent with as its caption, or the one it replies to.
+pub(crate) async fn process_reproof_command<B: Bot>(
+    games: &mut HashMap<String, Game>,
+    api: &mut B,
+    message: &Message,
+    rest: &str,
+    video: Option<&Message>,
+) -> Result<(), Error> {
+    let game = games
+        .entry(message.chat.id().to_string())
+        .or_insert(Default::default());
+
+    if game.finished() {
+        api.send(message.text_reply(FINISHED_REPLY)).await?;
+        return Ok(());
+    }
+
+    let video = match video.filter(|video| is_proof_media(video, &game.settings)) {
+        Some(video) => video,
+        None => {
+            api.send(message.text_reply(
+                "Новое видео нужно прислать с /reproof <№трюков> в подписи \
+                или ответить этой командой на него.",
+            ))
+            .await?;
+            return Ok(());
+        }
+    };
+
+    let mut tricks = match rest
+        .split(',')
+        .map(|trick| trick.trim().parse::<usize>())
+        .collect::<Result<Vec<usize>, _>>()
+    {
+        Ok(tricks) => tricks,
+        Err(_) => {
+            api.send(message.text_reply("Один или несколько номеров трюков указаны некорректно."))
+                .await?;
+            return Ok(());
+        }
+    };
+    tricks.sort_unstable();
+
+    let sender: GameUser = message.from.clone().into();
+    let new_msg: GameMessage = video.clone().into();
+    let new_file = VideoFile::of(video);
+    let is_used = game.find_participant_and_proof_by_msg(&new_msg).is_some()
+        || new_file
+            .as_ref()
+            .map_or(false, |file| game.find_proof_by_file(file).is_some());
+    if is_used {
+        api.send(message.text_reply("Это видео уже добавлено."))
+            .await?;
+        return Ok(());
+    }
+
+    let old_msg = game.participants.get(&sender).and_then(|participant| {
+        participant
+            .proofs
+            .iter()
+            .find(|proof| {
+                let mut proven = proof.tricks_proven.clone();
+                proven.sort_unstable();
+                proven == tricks
+            })
+            .map(|proof| proof.msg.clone())
+    });
+
+    let reply = match old_msg {
+        None => {
+            "У тебя нет пруфа ровно с этими трюками. \
+            Новые трюки добавляются командой /proof."
+        }
+        Some(old_msg) if is_challenged(game, &old_msg) => CHALLENGED_REPLY,
+        Some(old_msg) => {
+            game.replace_proof(&sender, &old_msg, new_msg, new_file);
+            update_game_message(api, &message.chat, game).await?;
+            persistence::mark_dirty(&message.chat);
+
+            "Пруф заменен новым видео, ссылка в закрепленном посте обновлена."
+        }
+    };
+
+    api.send(message.text_reply(reply)).await?;
+
+    Ok(())
+}
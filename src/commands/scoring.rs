@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::env;
+
+use indexmap::IndexMap;
+use telegram_bot::*;
+
+use crate::bot::Bot;
+use crate::commands::lifecycle::FINISHED_REPLY;
+use crate::types::*;
+use crate::{persistence, update_game_message};
+
+/// Points every trick is worth before the bonuses of the table.
+const BASE_POINTS: usize = 1;
+
+const POINTS_LIMITS: (usize, usize) = (1, 10);
+
+/// Bonus points for the parts of a trick name, used unless `SCORING_TABLE` is set.
+const DEFAULT_TABLE: &[(&str, usize)] = &[
+    ("flip", 1),
+    ("флип", 1),
+    ("180", 1),
+    ("360", 2),
+    ("540", 3),
+    ("varial", 1),
+    ("hard", 1),
+    ("inward", 1),
+    ("bigspin", 2),
+    ("impossible", 2),
+    ("laser", 2),
+    ("double", 2),
+    ("late", 2),
+    ("fakie", 1),
+    ("фейки", 1),
+    ("nollie", 1),
+    ("нолли", 1),
+    ("switch", 2),
+    ("свитч", 2),
+];
+
+lazy_static! {
+    static ref TABLE: Vec<(String, usize)> = load_table();
+}
+
+/// Reads the table from the YAML file of `keyword: points` pairs named by `SCORING_TABLE`,
+/// falling back to the default one.
+fn load_table() -> Vec<(String, usize)> {
+    let default_table = || {
+        DEFAULT_TABLE
+            .iter()
+            .map(|(keyword, points)| (keyword.to_string(), *points))
+            .collect()
+    };
+
+    let path = match env::var("SCORING_TABLE") {
+        Ok(path) => path,
+        Err(_) => return default_table(),
+    };
+
+    let table = std::fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|s| {
+            serde_yaml::from_str::<IndexMap<String, usize>>(&s).map_err(|e| e.to_string())
+        });
+    match table {
+        Ok(table) => table
+            .into_iter()
+            .map(|(keyword, points)| (keyword.to_lowercase(), points))
+            .collect(),
+        Err(e) => {
+            eprintln!(
+                "Scoring table {} load error, using the default: {}",
+                path, e
+            );
+            default_table()
+        }
+    }
+}
+
+/// Difficulty of the trick, by the parts of its name found in the scoring table.
+pub(crate) fn score(name: &str) -> usize {
+    let name = name.to_lowercase();
+    BASE_POINTS
+        + TABLE
+            .iter()
+            .filter(|(keyword, _)| name.contains(keyword.as_str()))
+            .map(|(_, points)| points)
+            .sum::<usize>()
+}
+
+pub(crate) async fn process_points_command<B: Bot>(
+    games: &mut HashMap<String, Game>,
+    api: &mut B,
+    message: &Message,
+    rest: &str,
+) -> Result<(), Error> {
+    let game = games
+        .entry(message.chat.id().to_string())
+        .or_insert(Default::default());
+
+    if game.finished() {
+        api.send(message.text_reply(FINISHED_REPLY)).await?;
+        return Ok(());
+    }
+
+    let args = rest
+        .split_whitespace()
+        .map(|arg| arg.parse::<usize>())
+        .collect::<Vec<_>>();
+    let (trick_id, points) = match args.as_slice() {
+        [Ok(trick_id), Ok(points)] => (*trick_id, *points),
+        _ => {
+            api.send(message.text_reply("Нужно указать номер трюка и количество очков."))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    if points < POINTS_LIMITS.0 || points > POINTS_LIMITS.1 {
+        api.send(message.text_reply(format!(
+            "Очков за трюк может быть от {} до {}.",
+            POINTS_LIMITS.0, POINTS_LIMITS.1
+        )))
+        .await?;
+        return Ok(());
+    }
+
+    match game.trick_by_id(trick_id) {
+        Some(trick) => {
+            game.set_trick_points(trick_id, points);
+            api.send(message.text_reply(format!(
+                "Трюк \"{}\" теперь стоит очков: {}.",
+                trick.name, points
+            )))
+            .await?;
+
+            update_game_message(api, &message.chat, game).await?;
+            persistence::mark_dirty(&message.chat);
+        }
+        None => {
+            api.send(message.text_reply("Трюк с указанным номером не найден!"))
+                .await?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+
+use telegram_bot::*;
+
+use crate::bot::Bot;
+use crate::types::*;
+use crate::voting::VotingPolicy;
+use crate::{is_admin, persistence, update_game_message};
+
+/// Callback data of the settings keyboard starts with this.
+pub(crate) const CALLBACK_PREFIX: &str = "settings,";
+
+const MAX_TRICKS_LIMITS: (usize, usize) = (1, 10);
+const MAX_RENAMES_LIMITS: (usize, usize) = (0, 5);
+const QUORUM_PERCENT_LIMITS: (usize, usize) = (10, 90);
+const QUORUM_PERCENT_STEP: usize = 10;
+/// Choices of the voting window, in hours.
+const VOTE_HOURS: &[usize] = &[1, 3, 6, 12, 24, 48, 72];
+const MIN_TURNOUT_PERCENT_LIMITS: (usize, usize) = (0, 50);
+const MIN_TURNOUT_PERCENT_STEP: usize = 10;
+const QUORUM_VOTES_LIMITS: (usize, usize) = (1, 10);
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "да"
+    } else {
+        "нет"
+    }
+}
+
+fn own_tricks_caption(own_tricks: OwnTricks) -> &'static str {
+    match own_tricks {
+        OwnTricks::Allowed => "можно",
+        OwnTricks::NotCounted => "без очков",
+        OwnTricks::Forbidden => "нельзя",
+    }
+}
+
+fn next_own_tricks(own_tricks: OwnTricks) -> OwnTricks {
+    match own_tricks {
+        OwnTricks::Allowed => OwnTricks::NotCounted,
+        OwnTricks::NotCounted => OwnTricks::Forbidden,
+        OwnTricks::Forbidden => OwnTricks::Allowed,
+    }
+}
+
+fn voting_caption(voting: VotingPolicy) -> &'static str {
+    match voting {
+        VotingPolicy::Majority => "большинство",
+        VotingPolicy::Supermajority => "отказ от 2/3 голосов",
+        VotingPolicy::FixedQuorum => "заданное число голосов",
+        VotingPolicy::AdminVeto => "решает администратор",
+    }
+}
+
+fn next_voting(voting: VotingPolicy) -> VotingPolicy {
+    match voting {
+        VotingPolicy::Majority => VotingPolicy::Supermajority,
+        VotingPolicy::Supermajority => VotingPolicy::FixedQuorum,
+        VotingPolicy::FixedQuorum => VotingPolicy::AdminVeto,
+        VotingPolicy::AdminVeto => VotingPolicy::Majority,
+    }
+}
+
+fn format_settings(settings: &GameSettings) -> String {
+    format!(
+        "Настройки игры:\n\
+        Трюков у участника: {max_tricks}\n\
+        Переименований трюка: {max_renames}\n\
+        Пруфы только видео: {videos_only}\n\
+        Кворум голосования: больше {quorum_percent}% участников\n\
+        Доказывать свои трюки: {own_tricks}\n\
+        Время на голосование: {vote_hours} ч\n\
+        Итог по истечении времени: если проголосовали от {min_turnout_percent}% участников\n\
+        Решение голосования: {voting}\n\
+        Голосов для решения (при заданном числе): {quorum_votes}\n\
+        Автор пруфа голосует: {owner_votes}",
+        max_tricks = settings.max_tricks,
+        max_renames = settings.max_renames,
+        videos_only = yes_no(settings.videos_only),
+        quorum_percent = settings.quorum_percent,
+        own_tricks = own_tricks_caption(settings.own_tricks),
+        vote_hours = settings.vote_hours,
+        min_turnout_percent = settings.min_turnout_percent,
+        voting = voting_caption(settings.voting),
+        quorum_votes = settings.quorum_votes,
+        owner_votes = yes_no(settings.owner_votes),
+    )
+}
+
+fn build_settings_keyboard(settings: &GameSettings) -> InlineKeyboardMarkup {
+    let videos_only_caption = format!("Только видео: {}", yes_no(settings.videos_only));
+    let own_tricks_caption = format!("Свои трюки: {}", own_tricks_caption(settings.own_tricks));
+    let voting_caption = format!("Решение: {}", voting_caption(settings.voting));
+    let owner_votes_caption = format!("Автор голосует: {}", yes_no(settings.owner_votes));
+
+    reply_markup!(inline_keyboard,
+        ["Трюков ➖" callback "settings,max_tricks,-", "Трюков ➕" callback "settings,max_tricks,+"],
+        ["Переименований ➖" callback "settings,max_renames,-", "Переименований ➕" callback "settings,max_renames,+"],
+        [videos_only_caption callback "settings,videos_only,toggle"],
+        ["Кворум ➖" callback "settings,quorum_percent,-", "Кворум ➕" callback "settings,quorum_percent,+"],
+        [own_tricks_caption callback "settings,own_tricks,next"],
+        ["Время ➖" callback "settings,vote_hours,-", "Время ➕" callback "settings,vote_hours,+"],
+        ["Явка ➖" callback "settings,min_turnout_percent,-", "Явка ➕" callback "settings,min_turnout_percent,+"],
+        [voting_caption callback "settings,voting,next"],
+        ["Голосов ➖" callback "settings,quorum_votes,-", "Голосов ➕" callback "settings,quorum_votes,+"],
+        [owner_votes_caption callback "settings,owner_votes,toggle"]
+    )
+}
+
+/// Steps the value by `step` in the direction of `change`, keeping it within the limits.
+fn step_value(value: usize, change: &str, step: usize, limits: (usize, usize)) -> usize {
+    match change {
+        "+" => (value + step).min(limits.1),
+        "-" => value.saturating_sub(step).max(limits.0),
+        _ => value,
+    }
+}
+
+/// Moves the value to the next or the previous one of the ascending `values`.
+fn step_in(values: &[usize], value: usize, change: &str) -> usize {
+    match change {
+        "+" => values.iter().copied().find(|v| *v > value).unwrap_or(value),
+        "-" => values
+            .iter()
+            .rev()
+            .copied()
+            .find(|v| *v < value)
+            .unwrap_or(value),
+        _ => value,
+    }
+}
+
+pub(crate) async fn process_settings_command<B: Bot>(
+    games: &mut HashMap<String, Game>,
+    api: &mut B,
+    message: &Message,
+) -> Result<(), Error> {
+    let game = games
+        .entry(message.chat.id().to_string())
+        .or_insert(Default::default());
+
+    api.send(
+        message
+            .text_reply(format_settings(&game.settings))
+            .reply_markup(build_settings_keyboard(&game.settings)),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn process_callback_query<B: Bot>(
+    games: &mut HashMap<String, Game>,
+    api: B,
+    cb: CallbackQuery,
+) -> Result<(), Error> {
+    let message = match &cb.message {
+        Some(MessageOrChannelPost::Message(message)) => message,
+        _ => return Ok(()),
+    };
+
+    if !is_admin(&cb.from) {
+        api.send(cb.answer("Менять настройки может только администратор."))
+            .await?;
+        return Ok(());
+    }
+
+    let data = cb.data.as_deref().unwrap_or("");
+    let data = data.split(',').collect::<Vec<_>>();
+    if data.len() != 3 {
+        return Ok(());
+    }
+    let (setting, change) = (data[1], data[2]);
+
+    let game = games
+        .entry(message.chat.id().to_string())
+        .or_insert(Default::default());
+    let settings = &mut game.settings;
+    let old_settings = settings.clone();
+
+    match setting {
+        "max_tricks" => {
+            settings.max_tricks = step_value(settings.max_tricks, change, 1, MAX_TRICKS_LIMITS)
+        }
+        "max_renames" => {
+            settings.max_renames = step_value(settings.max_renames, change, 1, MAX_RENAMES_LIMITS)
+        }
+        "videos_only" => settings.videos_only = !settings.videos_only,
+        "own_tricks" => settings.own_tricks = next_own_tricks(settings.own_tricks),
+        "quorum_percent" => {
+            settings.quorum_percent = step_value(
+                settings.quorum_percent,
+                change,
+                QUORUM_PERCENT_STEP,
+                QUORUM_PERCENT_LIMITS,
+            )
+        }
+        "vote_hours" => settings.vote_hours = step_in(VOTE_HOURS, settings.vote_hours, change),
+        "min_turnout_percent" => {
+            settings.min_turnout_percent = step_value(
+                settings.min_turnout_percent,
+                change,
+                MIN_TURNOUT_PERCENT_STEP,
+                MIN_TURNOUT_PERCENT_LIMITS,
+            )
+        }
+        "voting" => settings.voting = next_voting(settings.voting),
+        "quorum_votes" => {
+            settings.quorum_votes =
+                step_value(settings.quorum_votes, change, 1, QUORUM_VOTES_LIMITS)
+        }
+        "owner_votes" => settings.owner_votes = !settings.owner_votes,
+        _ => return Ok(()),
+    }
+
+    if *settings == old_settings {
+        api.send(cb.answer("Дальше менять некуда.")).await?;
+        return Ok(());
+    }
+
+    api.send(
+        message
+            .edit_text(format_settings(settings))
+            .reply_markup(build_settings_keyboard(settings)),
+    )
+    .await?;
+    api.send(cb.answer("Настройка изменена.")).await?;
+
+    // Which tricks score depends on the rules
+    if settings.own_tricks != old_settings.own_tricks && game.started() {
+        update_game_message(&mut api.clone(), &message.chat, game).await?;
+    }
+
+    persistence::mark_dirty(&message.chat);
+
+    Ok(())
+}
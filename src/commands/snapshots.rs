@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{TimeZone, Utc};
+use telegram_bot::*;
+
+use crate::bot::Bot;
+use crate::storage::{self, SnapshotInfo, SnapshotReason};
+use crate::time::{now, DAY_SECS};
+use crate::types::*;
+use crate::{persistence, update_game_message, GAMES};
+
+/// How many of the latest snapshots `/snapshots` lists.
+const MAX_LISTED_SNAPSHOTS: usize = 10;
+
+const DAILY_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+fn format_snapshot(snapshot: &SnapshotInfo) -> String {
+    format!(
+        "`{id}` — {time}, {reason}",
+        id = snapshot.id,
+        time = Utc
+            .timestamp(snapshot.id as i64, 0)
+            .format("%d.%m.%Y %H:%M UTC"),
+        reason = match snapshot.reason {
+            SnapshotReason::Reset => "перед сбросом",
+            SnapshotReason::Restore => "перед восстановлением",
+            SnapshotReason::Daily => "ежедневный",
+            SnapshotReason::Import => "перед импортом",
+        }
+    )
+}
+
+/// Keeps a snapshot of the chat's game, telling the chat if that failed.
+pub(crate) async fn take_snapshot<B: Bot>(
+    api: &mut B,
+    message: &Message,
+    game: &Game,
+    reason: SnapshotReason,
+) -> Result<Option<SnapshotInfo>, Error> {
+    match storage::save_snapshot(&message.chat.id().to_string(), game, reason).await {
+        Ok(snapshot) => Ok(Some(snapshot)),
+        Err(e) => {
+            eprintln!("Snapshot save error: {}", e);
+            api.send(message.text_reply("Не удалось сохранить снимок игры, отменяю."))
+                .await?;
+            Ok(None)
+        }
+    }
+}
+
+pub(crate) async fn process_snapshots_command<B: Bot>(
+    api: &mut B,
+    message: &Message,
+) -> Result<(), Error> {
+    let snapshots = match storage::list_snapshots(&message.chat.id().to_string()).await {
+        Ok(snapshots) => snapshots,
+        Err(e) => {
+            eprintln!("Snapshots list error: {}", e);
+            api.send(message.text_reply("Не удалось получить список снимков."))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    if snapshots.is_empty() {
+        api.send(message.text_reply("Снимков игры в этом чате пока нет."))
+            .await?;
+        return Ok(());
+    }
+
+    let snapshots = snapshots
+        .iter()
+        .rev()
+        .take(MAX_LISTED_SNAPSHOTS)
+        .map(format_snapshot)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    api.send(
+        message
+            .text_reply(format!(
+                "Снимки игры (восстановить: /restore <номер>):\n{}",
+                snapshots
+            ))
+            .parse_mode(ParseMode::Markdown),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn process_restore_command<B: Bot>(
+    games: &mut HashMap<String, Game>,
+    api: &mut B,
+    message: &Message,
+    rest: &str,
+) -> Result<(), Error> {
+    let id = match rest.trim().parse::<u64>() {
+        Ok(id) => id,
+        Err(_) => {
+            api.send(message.text_reply("Нужно указать номер снимка из /snapshots."))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let chat_id = message.chat.id().to_string();
+    let restored = match storage::load_snapshot(&chat_id, id).await {
+        Ok(Some(game)) => game,
+        Ok(None) => {
+            api.send(message.text_reply("Снимок с указанным номером не найден."))
+                .await?;
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("Snapshot load error: {}", e);
+            api.send(message.text_reply("Не удалось загрузить снимок."))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let game = games.entry(chat_id).or_insert(Default::default());
+
+    // Keep the current state too, so that restoring can be undone
+    let snapshot = match take_snapshot(api, message, game, SnapshotReason::Restore).await? {
+        Some(snapshot) => snapshot,
+        None => return Ok(()),
+    };
+
+    *game = restored;
+    update_game_message(api, &message.chat, game).await?;
+    persistence::mark_dirty(&message.chat);
+
+    api.send(message.text_reply(format!(
+        "Игра восстановлена из снимка {}. Прежнее состояние сохранено в снимок {}.",
+        id, snapshot.id
+    )))
+    .await?;
+
+    Ok(())
+}
+
+/// Starts the background task that keeps a daily snapshot of every started game that
+/// changed since its latest snapshot.
+pub(crate) fn spawn_daily() {
+    tokio::spawn(async {
+        // Time of the latest daily snapshot of each chat, or of the latest check that found
+        // the game unchanged, looked up on first use
+        let mut latest = HashMap::<String, u64>::new();
+
+        loop {
+            // Only copy the started games under the lock, the uploads are done without it
+            let games = GAMES
+                .lock()
+                .await
+                .iter()
+                .filter(|(_, game)| game.started())
+                .map(|(chat_id, game)| (chat_id.clone(), game.clone()))
+                .collect::<Vec<_>>();
+            for (chat_id, game) in &games {
+                let last = match latest.get(chat_id) {
+                    Some(last) => *last,
+                    None => match storage::list_snapshots(chat_id).await {
+                        Ok(snapshots) => snapshots
+                            .iter()
+                            .filter(|snapshot| snapshot.reason == SnapshotReason::Daily)
+                            .map(|snapshot| snapshot.id)
+                            .max()
+                            .unwrap_or(0),
+                        Err(e) => {
+                            eprintln!("Snapshots list error for chat {}: {}", chat_id, e);
+                            continue;
+                        }
+                    },
+                };
+
+                let now = now();
+                if now < last + DAY_SECS {
+                    latest.insert(chat_id.clone(), last);
+                    continue;
+                }
+
+                // Finished and idle games would only pile up copies of themselves
+                if is_unchanged(chat_id, game).await {
+                    latest.insert(chat_id.clone(), now);
+                    continue;
+                }
+
+                match storage::save_snapshot(chat_id, game, SnapshotReason::Daily).await {
+                    Ok(snapshot) => {
+                        latest.insert(chat_id.clone(), snapshot.id);
+                    }
+                    Err(e) => eprintln!("Daily snapshot error for chat {}: {}", chat_id, e),
+                }
+            }
+
+            tokio::time::delay_for(DAILY_CHECK_INTERVAL).await;
+        }
+    });
+}
+
+/// Whether the latest snapshot of the chat, whatever it was taken for, has the same game.
+async fn is_unchanged(chat_id: &str, game: &Game) -> bool {
+    let latest = match storage::list_snapshots(chat_id).await {
+        Ok(snapshots) => snapshots.last().cloned(),
+        Err(_) => None,
+    };
+
+    match latest {
+        Some(snapshot) => match storage::load_snapshot(chat_id, snapshot.id).await {
+            Ok(Some(saved)) => {
+                serde_yaml::to_string(&saved).ok() == serde_yaml::to_string(game).ok()
+            }
+            _ => false,
+        },
+        None => false,
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use telegram_bot::*;
+
+use crate::bot::Bot;
+use crate::commands::snapshots::take_snapshot;
+use crate::storage::{schema, SnapshotReason};
+use crate::types::*;
+use crate::{persistence, update_game_message};
+
+pub(crate) async fn process_export_command<B: Bot>(
+    games: &mut HashMap<String, Game>,
+    api: &mut B,
+    message: &Message,
+) -> Result<(), Error> {
+    let chat_id = message.chat.id().to_string();
+    let game = match games.get(&chat_id) {
+        Some(game) if game.started() => game,
+        _ => {
+            api.send(message.text_reply("В этом чате игры еще нет."))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let body = match schema::game_to_string(&chat_id, game) {
+        Ok(body) => body,
+        Err(e) => {
+            eprintln!("Game export error: {}", e);
+            api.send(message.text_reply("Не удалось выгрузить игру."))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let file = InputFileUpload::with_data(body.into_bytes(), format!("game-{}.yaml", chat_id));
+    api.send(message.document_reply(file).caption(
+        "Игра этого чата. Чтобы загрузить ее в другой чат, \
+        ответь там на этот файл командой /import.",
+    ))
+    .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn process_import_command<B: Bot>(
+    games: &mut HashMap<String, Game>,
+    api: &mut B,
+    message: &Message,
+) -> Result<(), Error> {
+    let document = message.reply_to_message.as_ref().and_then(|reply| {
+        if let MessageOrChannelPost::Message(msg) = reply.as_ref() {
+            if let MessageKind::Document { data, .. } = &msg.kind {
+                return Some(data.clone());
+            }
+        }
+        None
+    });
+
+    let document = match document {
+        Some(document) => document,
+        None => {
+            api.send(
+                message.text_reply("Нужно ответить командой на файл, выгруженный через /export."),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+
+    let imported = match download_game(api, &document).await? {
+        Some(game) => game,
+        None => {
+            api.send(message.text_reply("Не удалось прочитать игру из этого файла."))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let game = games
+        .entry(message.chat.id().to_string())
+        .or_insert(Default::default());
+
+    // Keep the current state, so that an import into the wrong chat can be undone
+    if game.started()
+        && take_snapshot(api, message, game, SnapshotReason::Import)
+            .await?
+            .is_none()
+    {
+        return Ok(());
+    }
+
+    *game = imported;
+    update_game_message(api, &message.chat, game).await?;
+    persistence::mark_dirty(&message.chat);
+
+    api.send(message.text_reply("Игра загружена в этот чат."))
+        .await?;
+
+    Ok(())
+}
+
+/// Downloads the exported game, `None` if the file can't be fetched or isn't a game.
+async fn download_game<B: Bot>(api: &mut B, document: &Document) -> Result<Option<Game>, Error> {
+    let body = match api.download(document).await? {
+        Some(body) => body,
+        None => return Ok(None),
+    };
+
+    match schema::single_game_from_reader(body.as_slice()) {
+        Ok(Some(mut game)) => {
+            // Messages of the game and the polls belong to the chat it was exported from.
+            // A repeat under a vote there counts, like it does when too few voted.
+            game.game_message = None;
+            game.proof_challenges.clear();
+            if let Some(duel) = game.duel.as_mut() {
+                duel.dispute = None;
+                duel.landed_trick = None;
+            }
+            Ok(Some(game))
+        }
+        Ok(None) => Ok(None),
+        Err(e) => {
+            eprintln!("Imported game parse error: {}", e);
+            Ok(None)
+        }
+    }
+}
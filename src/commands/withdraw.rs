@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+use telegram_bot::*;
+
+use crate::bot::{self, Bot};
+use crate::commands::lifecycle::FINISHED_REPLY;
+use crate::commands::{challenge, duel};
+use crate::types::*;
+use crate::{persistence, update_game_message};
+
+pub(crate) async fn process_untrick_command<B: Bot>(
+    games: &mut HashMap<String, Game>,
+    api: &mut B,
+    message: &Message,
+    rest: &str,
+) -> Result<(), Error> {
+    let game = games
+        .entry(message.chat.id().to_string())
+        .or_insert(Default::default());
+
+    if game.finished() {
+        api.send(message.text_reply(FINISHED_REPLY)).await?;
+        return Ok(());
+    }
+
+    let trick_id = match rest.trim().parse::<usize>() {
+        Ok(trick_id) => trick_id,
+        Err(_) => {
+            api.send(message.text_reply("Неверно указан номер трюка."))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let sender: GameUser = message.from.clone().into();
+    let reply = match (game.trick_owner(trick_id), game.trick_by_id(trick_id)) {
+        (Some(owner), Some(trick)) => {
+            if owner != sender {
+                "Убирать можно только свои трюки.".to_owned()
+            } else if game.is_trick_referenced(trick_id) {
+                "У трюка уже есть пруфы, убрать его нельзя.".to_owned()
+            } else {
+                game.remove_trick(trick_id);
+                update_game_message(api, &message.chat, game).await?;
+                persistence::mark_dirty(&message.chat);
+
+                format!("Трюк \"{}\" убран.", trick.name)
+            }
+        }
+        _ => "Трюк с указанным номером не найден!".to_owned(),
+    };
+
+    api.send(message.text_reply(reply)).await?;
+
+    Ok(())
+}
+
+pub(crate) async fn process_leave_command<B: Bot>(
+    games: &mut HashMap<String, Game>,
+    api: &mut B,
+    message: &Message,
+) -> Result<(), Error> {
+    let game = games
+        .entry(message.chat.id().to_string())
+        .or_insert(Default::default());
+
+    if game.finished() {
+        api.send(message.text_reply(FINISHED_REPLY)).await?;
+        return Ok(());
+    }
+
+    let sender: GameUser = message.from.clone().into();
+    let own_tricks = match game.participant_tricks(&sender) {
+        Some(tricks) => tricks.iter().map(|trick| trick.id).collect::<Vec<_>>(),
+        None => {
+            api.send(message.text_reply("Ты не участвуешь в игре."))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    // Votes on proofs of the participant, or on their tricks, have nothing left to decide
+    let affected_challenges = game
+        .proof_challenges
+        .iter()
+        .filter(|(_, challenge)| {
+            challenge.user == sender
+                || challenge
+                    .proof
+                    .tricks_proven
+                    .iter()
+                    .any(|id| own_tricks.contains(id))
+        })
+        .map(|(msg, _)| msg.clone())
+        .collect::<Vec<_>>();
+    for msg in affected_challenges {
+        if let Some(challenge) = game.proof_challenges.shift_remove(&msg) {
+            let poll_msg: MessageOrChannelPost = challenge.poll_msg.into();
+            // The poll may be gone already, the game is updated anyway
+            let _ = api
+                .send(poll_msg.edit_text("Голосование отменено: участник вышел из игры."))
+                .await;
+        }
+    }
+
+    // The other votes may be enough with fewer left to vote
+    game.remove_participant(&sender);
+    let mut outgoing = vec![];
+    challenge::recount_votes(game, &mut outgoing);
+    duel::recount_dispute(message.chat.id(), game, &mut outgoing);
+    bot::send_all(api, outgoing).await?;
+    update_game_message(api, &message.chat, game).await?;
+    persistence::mark_dirty(&message.chat);
+
+    api.send(message.text_reply(
+        "Ты вышел из игры. Твои трюки и пруфы убраны, как и пруфы других участников, \
+        в которых были только твои трюки.",
+    ))
+    .await?;
+
+    Ok(())
+}
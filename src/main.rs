@@ -12,33 +12,38 @@ use tokio::sync::Mutex;
 mod types;
 use types::*;
 
+mod bot;
 mod commands;
-mod dropbox;
+mod persistence;
+mod storage;
+mod time;
+mod voting;
 
-use commands::challenge;
+use bot::{Bot, Outgoing, TelegramBot};
+use commands::{
+    challenge, deadline, duel, lifecycle, proofs, scoring, settings, snapshots, transfer, withdraw,
+};
+use storage::SnapshotReason;
 
 lazy_static! {
     static ref GAMES: Mutex<HashMap<String, Game>> = Mutex::new(Default::default());
 }
 
-const MAX_TRICKS: usize = 3;
-
 fn format_game_message(game: &Game) -> String {
     let participants = game
         .participants
         .iter()
-        .enumerate()
-        .map(|(participant_index, (participant_user, participant))| {
+        .map(|(participant_user, participant)| {
             let tricks = participant
                 .tricks
                 .iter()
-                .enumerate()
-                .map(|(i, trick)| {
+                .map(|trick| {
                     format!(
-                        "{}. {}{}",
-                        participant_index * MAX_TRICKS + i + 1,
-                        if trick.edited { "📝" } else { "" },
-                        escape_markdown(&trick.name)
+                        "{}. {}{} ({})",
+                        trick.id,
+                        if trick.edited() { "📝" } else { "" },
+                        escape_markdown(&trick.name),
+                        trick.points
                     )
                 })
                 .collect::<Vec<String>>()
@@ -58,18 +63,15 @@ fn format_game_message(game: &Game) -> String {
         .collect::<Vec<String>>()
         .join("\n");
 
-    let mut leaderboard = game.participants.iter().collect::<Vec<_>>();
-    leaderboard.sort_by(|(_, participant_a), (_, participant_b)| {
-        let num_tricks_b: usize = participant_b.num_tricks_proven();
-        let num_tricks_a: usize = participant_a.num_tricks_proven();
-
-        num_tricks_b.cmp(&num_tricks_a)
-    });
-
-    let leaderboard = leaderboard
+    let leaderboard = game
+        .standings()
         .into_iter()
-        .enumerate()
-        .map(|(i, (user, participant))| {
+        .flat_map(|(place, user, points)| {
+            game.participants
+                .get(&user)
+                .map(|participant| (place, user, points, participant))
+        })
+        .map(|(place, user, points, participant)| {
             let proofs = if participant.proofs.is_empty() {
                 "".to_owned()
             } else {
@@ -87,12 +89,15 @@ fn format_game_message(game: &Game) -> String {
                     .collect::<Vec<String>>()
                     .join("");
 
-                format!(" | Пруфы: {} (трюков: {})", proofs, num_tricks)
+                format!(
+                    " | Пруфы: {} (очков: {}, трюков: {})",
+                    proofs, points, num_tricks
+                )
             };
 
             format!(
                 "{}. {firstname} {name}{proofs}",
-                i + 1,
+                place,
                 firstname = escape_markdown(&user.first_name),
                 name = user
                     .username
@@ -106,20 +111,38 @@ fn format_game_message(game: &Game) -> String {
         .join("\n");
 
     format!(
-        "=== Game of Skate ===\n\n\
+        "=== Game of Skate{finished} ===\n\n\
+        {deadline}\
         {participants}\
         \n\n\
         === Leaderboard ===\n\n\
         {leaderboard}\
+        {duel}\
         ",
+        finished = if game.finished() {
+            " (завершена)"
+        } else {
+            ""
+        },
+        deadline = match game.deadline {
+            Some(deadline) if !game.finished() => {
+                format!("⏰ Игра до: {}\n\n", deadline::format_deadline(deadline))
+            }
+            _ => "".to_owned(),
+        },
         participants = participants,
         leaderboard = leaderboard,
+        duel = game
+            .duel
+            .as_ref()
+            .map(|duel| format!("\n\n{}", duel::format_duel(duel)))
+            .unwrap_or_default(),
     )
 }
 
-pub(crate) async fn update_game_message(
-    api: &mut Api,
-    chat: &MessageChat,
+pub(crate) async fn update_game_message<B: Bot, C: ToChatRef>(
+    api: &mut B,
+    chat: &C,
     game: &mut Game,
 ) -> Result<(), Error> {
     let game_message_text = format_game_message(game);
@@ -153,6 +176,59 @@ pub(crate) async fn update_game_message(
     Ok(())
 }
 
+/// The edit bringing the game message up to date, for a game that was changed under the
+/// lock on the games and is shown without it. A game without its message gets one on the
+/// next update.
+pub(crate) fn game_message_update(game: &Game) -> Option<Outgoing> {
+    game.game_message().map(|msg| Outgoing::GameMessage {
+        msg,
+        text: format_game_message(game),
+    })
+}
+
+/// Moves the game of a group that was upgraded to a supergroup to the new chat id,
+/// along with its snapshots. Telegram reports the migration in both chats, whichever
+/// comes first does the move.
+///
+/// Returns whether the game was left in the old chat because a game was already started
+/// in the new one.
+async fn migrate_game(from_chat_id: i64, to_chat_id: i64) -> bool {
+    let (from_key, to_key) = (from_chat_id.to_string(), to_chat_id.to_string());
+    {
+        let mut games = GAMES.lock().await;
+
+        // Don't replace a game that was already started in the new chat
+        if games.get(&to_key).map_or(false, |game| game.started()) {
+            return games.get(&from_key).map_or(false, |game| game.started());
+        }
+
+        match games.remove(&from_key) {
+            Some(mut game) => {
+                game.move_to_chat(to_chat_id);
+                games.insert(to_key.clone(), game);
+            }
+            None => return false,
+        }
+    }
+
+    persistence::mark_chat_dirty(from_key.clone());
+    persistence::mark_chat_dirty(to_key);
+
+    if let Err(e) = storage::move_snapshots(&from_key, to_chat_id).await {
+        eprintln!(
+            "Snapshots move error from chat {} to chat {}: {}",
+            from_key, to_chat_id, e
+        );
+    }
+
+    false
+}
+
+/// Whether the user may run the commands that can wipe or replace a game.
+pub(crate) fn is_admin(user: &User) -> bool {
+    user.username.as_deref() == Some("eupn1337")
+}
+
 fn extract_command(s: &str) -> Option<(String, String)> {
     let words = s.split(" ").collect::<Vec<_>>();
     if words.len() == 0 {
@@ -165,14 +241,20 @@ fn extract_command(s: &str) -> Option<(String, String)> {
     Some((command.to_owned(), rest))
 }
 
-async fn add_proof(
+async fn add_proof<B: Bot>(
     should_accept: bool,
     message: &Message,
     rest: &str,
     sender: &GameUser,
-    api: &mut Api,
+    api: &mut B,
     game: &mut Game,
 ) -> Result<(), Error> {
+    if game.finished() {
+        api.send(message.text_reply(lifecycle::FINISHED_REPLY))
+            .await?;
+        return Ok(());
+    }
+
     if game.started() {
         if game.participant_tricks(&sender).is_some() {
             if let Some((user, _, _)) =
@@ -193,6 +275,24 @@ async fn add_proof(
                     return Ok(());
                 }
 
+                // The same clip forwarded or uploaded again comes in a new message
+                let used_by = VideoFile::of(message)
+                    .and_then(|file| game.find_proof_by_file(&file))
+                    .map(|(user, _)| user);
+                if let Some(user) = used_by {
+                    let reply = if user == *sender {
+                        "Это видео уже добавлено.".to_owned()
+                    } else {
+                        format!(
+                            "Это видео уже использовано в пруфе участника {}.",
+                            user.first_name
+                        )
+                    };
+                    api.send(message.text_reply(reply)).await?;
+
+                    return Ok(());
+                }
+
                 let tricks = rest
                     .split(",")
                     .into_iter()
@@ -201,6 +301,23 @@ async fn add_proof(
 
                 match tricks {
                     Ok(tricks) => {
+                        let own_tricks = tricks
+                            .iter()
+                            .filter(|trick| game.trick_owner(**trick).as_ref() == Some(sender))
+                            .map(|trick| format!("№{}", trick))
+                            .collect::<Vec<_>>();
+                        if !own_tricks.is_empty()
+                            && game.settings.own_tricks == OwnTricks::Forbidden
+                        {
+                            api.send(message.text_reply(format!(
+                                "По правилам этой игры свои трюки доказывать нельзя (/settings). \
+                                Твои трюки в пруфе: {}.",
+                                own_tricks.join(", ")
+                            )))
+                            .await?;
+                            return Ok(());
+                        }
+
                         let not_proven_tricks = tricks
                             .clone()
                             .into_iter()
@@ -214,8 +331,7 @@ async fn add_proof(
 
                         for trick in &already_proven_tricks {
                             if game.is_trick_proven(&sender, *trick) {
-                                let trick_name =
-                                    game.trick_by_number(*trick).map(|trick| trick.name);
+                                let trick_name = game.trick_by_id(*trick).map(|trick| trick.name);
 
                                 api.send(message.text_reply(format!(
                                     "У тебя трюк {}уже имеет пруф! Не добавляю.",
@@ -250,8 +366,18 @@ async fn add_proof(
                             .join("\n");
                         update_game_message(api, &message.chat, game).await?;
                         api.send(
-                            message.text_reply(format!("Видео-доказательство трюка добавлено в закрепленный пост. Относится к трюкам:\n{tricks}",
+                            message.text_reply(format!("Видео-доказательство трюка добавлено в закрепленный пост. Относится к трюкам:\n{tricks}{note}",
                                 tricks = tricks_proven,
+                                note = if !own_tricks.is_empty()
+                                    && game.settings.own_tricks == OwnTricks::NotCounted
+                                {
+                                    format!(
+                                        "\n\nПо правилам этой игры свои трюки ({}) очков не приносят.",
+                                        own_tricks.join(", ")
+                                    )
+                                } else {
+                                    "".to_owned()
+                                },
                             )),
                         )
                             .await?;
@@ -266,9 +392,12 @@ async fn add_proof(
                     }
                 }
             } else {
-                api.send(message.text_reply(
-                    "В качестве доказательства принимаются только видео либо ответ на видео.",
-                ))
+                api.send(message.text_reply(if game.settings.videos_only {
+                    "В качестве доказательства принимаются только видео либо ответ на видео."
+                } else {
+                    "В качестве доказательства принимаются только видео, фото или файлы \
+                    либо ответ на них."
+                }))
                 .await?;
             }
         } else {
@@ -287,9 +416,13 @@ async fn add_proof(
     Ok(())
 }
 
-fn is_video(message: &Message) -> (bool, Option<String>) {
+/// Whether the message is a video, along with the caption of a captioned video, document
+/// or photo.
+pub(crate) fn is_video(message: &Message) -> (bool, Option<String>) {
     match message.clone().kind {
-        MessageKind::Video { .. } | MessageKind::VideoNote { .. } => (true, None),
+        MessageKind::Video { caption, .. } => (true, caption),
+        MessageKind::VideoNote { .. } => (true, None),
+        MessageKind::Photo { caption, .. } => (false, caption),
 
         MessageKind::Document { data, caption, .. } => data
             .mime_type
@@ -299,9 +432,42 @@ fn is_video(message: &Message) -> (bool, Option<String>) {
     }
 }
 
-async fn process_message(mut api: Api, message: Message) -> Result<(), Error> {
+/// Whether the message can prove tricks in the game: a video, or also a photo or any
+/// document when the game doesn't take only videos.
+pub(crate) fn is_proof_media(message: &Message, settings: &GameSettings) -> bool {
+    let is_media = matches!(
+        message.kind,
+        MessageKind::Photo { .. } | MessageKind::Document { .. }
+    );
+    is_video(message).0 || (is_media && !settings.videos_only)
+}
+
+async fn process_message<B: Bot>(mut api: B, message: Message) -> Result<(), Error> {
     let sender = &message.from;
 
+    match message.kind {
+        MessageKind::MigrateToChatId { data } => {
+            migrate_game(message.chat.id().into(), data).await;
+            return Ok(());
+        }
+        MessageKind::MigrateFromChatId { data } => {
+            // Told only here, the message in the old chat reports the same migration
+            if migrate_game(data, message.chat.id().into()).await {
+                eprintln!(
+                    "Game of chat {} not moved to chat {}, a game is started there already",
+                    data,
+                    message.chat.id()
+                );
+                api.send(message.chat.text(
+                    "Игра из группы не перенесена в супергруппу: здесь уже идет другая игра.",
+                ))
+                .await?;
+            }
+            return Ok(());
+        }
+        _ => (),
+    }
+
     if let MessageKind::Text { ref data, .. } = message.kind {
         let res = extract_command(&data);
         if res.is_none() {
@@ -316,15 +482,98 @@ async fn process_message(mut api: Api, message: Message) -> Result<(), Error> {
 
         match command.to_lowercase().as_str() {
             "/reset" => {
-                if let Some(username) = &sender.username {
-                    if username == "eupn1337" {
-                        let mut games = GAMES.lock().await;
-                        let game = games
-                            .entry(message.chat.id().to_string())
-                            .or_insert(Default::default());
-                        *game = Default::default();
-                        dropbox::save_games(&games).await;
+                if is_admin(sender) {
+                    let mut games = GAMES.lock().await;
+                    let game = games
+                        .entry(message.chat.id().to_string())
+                        .or_insert(Default::default());
+
+                    if game.started() {
+                        let snapshot = snapshots::take_snapshot(
+                            &mut api,
+                            &message,
+                            game,
+                            SnapshotReason::Reset,
+                        )
+                        .await?;
+
+                        match snapshot {
+                            Some(snapshot) => {
+                                api.send(message.text_reply(format!(
+                                    "Игра сброшена. Вернуть ее: /restore {}",
+                                    snapshot.id
+                                )))
+                                .await?;
+                            }
+                            None => return Ok(()),
+                        }
                     }
+
+                    game.clear();
+                    persistence::mark_dirty(&message.chat);
+                }
+            }
+
+            "/snapshots" => {
+                snapshots::process_snapshots_command(&mut api, &message).await?;
+            }
+
+            "/restore" => {
+                if is_admin(sender) {
+                    let mut games = GAMES.lock().await;
+                    snapshots::process_restore_command(&mut games, &mut api, &message, &rest)
+                        .await?;
+                }
+            }
+
+            "/settings" => {
+                if is_admin(sender) {
+                    let mut games = GAMES.lock().await;
+                    settings::process_settings_command(&mut games, &mut api, &message).await?;
+                }
+            }
+
+            "/points" => {
+                if is_admin(sender) {
+                    let mut games = GAMES.lock().await;
+                    scoring::process_points_command(&mut games, &mut api, &message, &rest).await?;
+                }
+            }
+
+            "/deadline" => {
+                if is_admin(sender) {
+                    let mut games = GAMES.lock().await;
+                    deadline::process_deadline_command(&mut games, &mut api, &message, &rest)
+                        .await?;
+                }
+            }
+
+            "/endgame" => {
+                if is_admin(sender) {
+                    let mut games = GAMES.lock().await;
+                    lifecycle::process_endgame_command(&mut games, &mut api, &message).await?;
+                }
+            }
+
+            "/newgame" => {
+                let mut games = GAMES.lock().await;
+                lifecycle::process_newgame_command(&mut games, &mut api, &message).await?;
+            }
+
+            "/history" => {
+                let mut games = GAMES.lock().await;
+                lifecycle::process_history_command(&mut games, &mut api, &message).await?;
+            }
+
+            "/export" => {
+                let mut games = GAMES.lock().await;
+                transfer::process_export_command(&mut games, &mut api, &message).await?;
+            }
+
+            "/import" => {
+                if is_admin(sender) {
+                    let mut games = GAMES.lock().await;
+                    transfer::process_import_command(&mut games, &mut api, &message).await?;
                 }
             }
 
@@ -341,12 +590,18 @@ async fn process_message(mut api: Api, message: Message) -> Result<(), Error> {
                 let mut game = games
                     .entry(message.chat.id().to_string())
                     .or_insert(Default::default());
+                if game.finished() {
+                    api.send(message.text_reply(lifecycle::FINISHED_REPLY))
+                        .await?;
+                    return Ok(());
+                }
+                let max_tricks = game.settings.max_tricks;
 
                 match game.participant_tricks(&sender.clone().into()) {
-                    Some(tricks) if tricks.len() >= MAX_TRICKS => {
+                    Some(tricks) if tricks.len() >= max_tricks => {
                         api.send(message.text_reply(format!(
                             "У тебя все трюки уже добавлены (максимум {})",
-                            MAX_TRICKS
+                            max_tricks
                         )))
                         .await?;
                     }
@@ -365,14 +620,14 @@ async fn process_message(mut api: Api, message: Message) -> Result<(), Error> {
                                 .participant_tricks(&sender.clone().into())
                                 .map(|tricks| tricks.len())
                                 .unwrap_or(0);
-                            if num_tricks >= MAX_TRICKS {
+                            if num_tricks >= max_tricks {
                                 break;
                             }
 
                             let trick = trick.trim();
-                            game.add_trick(&sender.clone().into(), trick);
+                            game.add_trick(&sender.clone().into(), trick, scoring::score(trick));
 
-                            let remaining_tricks = MAX_TRICKS - num_tricks - 1;
+                            let remaining_tricks = max_tricks - num_tricks - 1;
                             let footer = if remaining_tricks == 0 {
                                 "Больше трюки добавлять нельзя.".to_owned()
                             } else {
@@ -398,7 +653,7 @@ async fn process_message(mut api: Api, message: Message) -> Result<(), Error> {
                 }
 
                 update_game_message(&mut api, &message.chat, &mut game).await?;
-                dropbox::save_games(&games).await;
+                persistence::mark_dirty(&message.chat);
             }
 
             "/proof" | "/пруф" => {
@@ -411,8 +666,8 @@ async fn process_message(mut api: Api, message: Message) -> Result<(), Error> {
                     .reply_to_message
                     .map(|reply| {
                         if let MessageOrChannelPost::Message(msg) = *reply {
-                            let (is_vid, _) = is_video(&msg);
-                            (Some(msg), is_vid)
+                            let should_accept = is_proof_media(&msg, &game.settings);
+                            (Some(msg), should_accept)
                         } else {
                             (None, false)
                         }
@@ -429,7 +684,7 @@ async fn process_message(mut api: Api, message: Message) -> Result<(), Error> {
                     )
                     .await?;
 
-                    dropbox::save_games(&games).await;
+                    persistence::mark_dirty(&message.chat);
                 } else {
                     add_proof(
                         false,
@@ -464,18 +719,26 @@ async fn process_message(mut api: Api, message: Message) -> Result<(), Error> {
                     return Ok(());
                 }
 
-                let trick_index = trick_no - 1;
                 let mut games = GAMES.lock().await;
                 let mut game = games
                     .entry(message.chat.id().to_string())
                     .or_insert(Default::default());
-                let participant_index = trick_index / MAX_TRICKS;
-                if let Some(user) = game.user_by_index(participant_index) {
+                if game.finished() {
+                    api.send(message.text_reply(lifecycle::FINISHED_REPLY))
+                        .await?;
+                    return Ok(());
+                }
+
+                if let Some(user) = game.trick_owner(trick_no) {
                     if user.id != i64::from(message.from.id) {
+                        let own_tricks = game
+                            .participant_tricks(&message.from.clone().into())
+                            .unwrap_or_default();
                         api.send(message.text_reply(format!(
                             "Можно переименовывать только свои трюки ({}).",
-                            (trick_index..(trick_index + MAX_TRICKS)).into_iter()
-                                .map(|n| format!("№{}", n + 1))
+                            own_tricks
+                                .iter()
+                                .map(|trick| format!("№{}", trick.id))
                                 .collect::<Vec<_>>()
                                 .join(", ")
                         )))
@@ -484,21 +747,24 @@ async fn process_message(mut api: Api, message: Message) -> Result<(), Error> {
                     }
 
                     let new_trick_name = rest.into_iter().skip(1).collect::<Vec<_>>().join(" ");
-                    match game.trick_by_number(trick_no) {
+                    match game.trick_by_id(trick_no) {
                         Some(trick) => {
-                            if trick.edited {
-                                api.send(
-                                    message.text_reply("Трюк уже переименовывался, больше нельзя."),
-                                )
+                            if trick.renames >= game.settings.max_renames {
+                                api.send(message.text_reply(if trick.edited() {
+                                    "Трюк уже переименовывался, больше нельзя."
+                                } else {
+                                    "Переименовывать трюки в этой игре нельзя."
+                                }))
                                 .await?;
                                 return Ok(());
                             }
 
-                            game.update_trick_name(trick_index, new_trick_name);
+                            let points = scoring::score(&new_trick_name);
+                            game.update_trick_name(trick_no, new_trick_name, points);
                             api.send(message.text_reply("Трюк переименован!")).await?;
 
                             update_game_message(&mut api, &message.chat, &mut game).await?;
-                            dropbox::save_games(&games).await;
+                            persistence::mark_dirty(&message.chat);
                         }
                         None => {
                             api.send(message.text_reply("Трюк с указанным номером не найден!"))
@@ -506,9 +772,49 @@ async fn process_message(mut api: Api, message: Message) -> Result<(), Error> {
                             return Ok(());
                         }
                     }
+                } else {
+                    api.send(message.text_reply("Трюк с указанным номером не найден!"))
+                        .await?;
                 }
             }
 
+            "/duel" => {
+                let video = message
+                    .reply_to_message
+                    .as_deref()
+                    .and_then(|reply| match reply {
+                        MessageOrChannelPost::Message(reply) if is_video(reply).0 => Some(reply),
+                        _ => None,
+                    });
+                let mut games = GAMES.lock().await;
+                duel::process_duel_command(&mut games, &mut api, &message, &rest, video).await?;
+            }
+
+            "/unproof" => {
+                let mut games = GAMES.lock().await;
+                proofs::process_unproof_command(&mut games, &mut api, &message).await?;
+            }
+
+            "/reproof" => {
+                let video = match message.reply_to_message.as_deref() {
+                    Some(MessageOrChannelPost::Message(reply)) => Some(reply),
+                    _ => None,
+                };
+                let mut games = GAMES.lock().await;
+                proofs::process_reproof_command(&mut games, &mut api, &message, &rest, video)
+                    .await?;
+            }
+
+            "/untrick" => {
+                let mut games = GAMES.lock().await;
+                withdraw::process_untrick_command(&mut games, &mut api, &message, &rest).await?;
+            }
+
+            "/leave" => {
+                let mut games = GAMES.lock().await;
+                withdraw::process_leave_command(&mut games, &mut api, &message).await?;
+            }
+
             "/challenge" => {
                 let mut games = GAMES.lock().await;
                 challenge::process_challenge_command(&mut games, &mut api, &message).await?;
@@ -544,18 +850,32 @@ async fn process_message(mut api: Api, message: Message) -> Result<(), Error> {
                 /trick <трюк1> - добавить один трюк\n\
                 /trick <трюк1, трюк2, трюк3> - добавить сразу несколько\n\
                 /edit <№трюка> <новое название> - редактировать трюк (не более одного раза)\n\
+                /untrick <№трюка> - убрать свой трюк, пока на него нет пруфов\n\
+                /leave - выйти из игры\n\
                 /proof - в комментарии к прикрепленному видео или в ответе на видео, \
                 чтобы приобщить его в качестве доказательства\n\
+                /unproof - в ответе на свое видео-доказательство, чтобы убрать его\n\
+                /reproof <№трюков> - в комментарии к новому видео или в ответе на него, \
+                чтобы заменить им свой пруф тех же трюков\n\
                 /challenge - в комментарии к видео-доказательству чтобы запустить голосование \
                 против доказательства\n\
-                /random - сгенерировать случайный трюк",
+                /random - сгенерировать случайный трюк\n\
+                /duel @username - вызвать на дуэль S.K.A.T.E.\n\
+                /newgame - начать новую игру после завершения прошлой\n\
+                /history - итоги завершенных игр\n\
+                /snapshots - список сохраненных снимков игры\n\
+                /settings - настройки игры (для администратора)\n\
+                /points <№трюка> <очки> - изменить сложность трюка (для администратора)\n\
+                /deadline <дд.мм.гггг [чч:мм]> - срок, когда игра закроется сама (для администратора)\n\
+                /export - выгрузить игру этого чата файлом\n\
+                /import - в ответе на выгруженный файл, чтобы загрузить игру в этот чат",
                 ))
                 .await?;
             }
         }
     } else {
         let (is_vid, caption) = is_video(&message);
-        if !is_vid || caption.is_none() {
+        if caption.is_none() {
             return Ok(());
         }
 
@@ -577,6 +897,10 @@ async fn process_message(mut api: Api, message: Message) -> Result<(), Error> {
                 let mut game = games
                     .entry(message.chat.id().to_string())
                     .or_insert(Default::default());
+                if !is_vid && game.settings.videos_only {
+                    return Ok(());
+                }
+
                 add_proof(
                     true,
                     &message,
@@ -588,7 +912,25 @@ async fn process_message(mut api: Api, message: Message) -> Result<(), Error> {
                 .await?;
 
                 update_game_message(&mut api, &message.chat, &mut game).await?;
-                dropbox::save_games(&games).await;
+                persistence::mark_dirty(&message.chat);
+            }
+
+            "/reproof" => {
+                let mut games = GAMES.lock().await;
+                proofs::process_reproof_command(
+                    &mut games,
+                    &mut api,
+                    &message,
+                    &rest,
+                    Some(&message),
+                )
+                .await?;
+            }
+
+            "/duel" => {
+                let video = if is_vid { Some(&message) } else { None };
+                let mut games = GAMES.lock().await;
+                duel::process_duel_command(&mut games, &mut api, &message, &rest, video).await?;
             }
             _ => (),
         }
@@ -596,37 +938,95 @@ async fn process_message(mut api: Api, message: Message) -> Result<(), Error> {
     Ok(())
 }
 
+/// Passes the update to its handler. Errors of the handlers are dropped, the update is
+/// not handled again.
+async fn process_update<B: Bot>(api: B, update: Update) {
+    match update.kind {
+        UpdateKind::Message(message) => {
+            let _ = process_message(api, message).await;
+        }
+
+        UpdateKind::CallbackQuery(cb) => {
+            let mut games = GAMES.lock().await;
+            let data = cb.data.clone().unwrap_or_default();
+            let _ = if data.starts_with(settings::CALLBACK_PREFIX) {
+                settings::process_callback_query(&mut games, api, cb).await
+            } else if data.starts_with(duel::CALLBACK_PREFIX) {
+                duel::process_callback_query(&mut games, api, cb).await
+            } else {
+                challenge::process_callback_query(&mut games, api, cb).await
+            };
+        }
+
+        _ => (),
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     let token = env::var("TELEGRAM_BOT_TOKEN").expect("TELEGRAM_BOT_TOKEN not set");
 
     // Load saved games
-    {
-        let mut games = GAMES.lock().await;
-        *games = dropbox::load_games().await;
+    match storage::load_games().await {
+        Ok(loaded_games) => {
+            let mut games = GAMES.lock().await;
+            *games = loaded_games;
+        }
+        Err(e) => eprintln!("Saved games load error, saving is disabled: {}", e),
     }
 
-    let api = Api::new(token);
+    let api = TelegramBot::new(token);
+    persistence::spawn(api.clone());
+    snapshots::spawn_daily();
+    deadline::spawn(api.clone());
+    challenge::spawn(api.clone());
+
     let mut stream = api.stream();
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
 
     // Fetch new updates via long poll method
-    while let Some(update) = stream.next().await {
-        let update = update?;
-        match update.kind {
-            UpdateKind::Message(message) => {
-                let _ = process_message(api.clone(), message).await;
+    loop {
+        let update = tokio::select! {
+            update = stream.next() => update,
+            _ = &mut shutdown => break,
+        };
+
+        let update = match update {
+            Some(Ok(update)) => update,
+            Some(Err(e)) => {
+                persistence::flush().await;
+                return Err(e);
             }
+            None => break,
+        };
 
-            UpdateKind::CallbackQuery(cb) => {
-                let mut games = GAMES.lock().await;
-                let _ = challenge::process_callback_query(&mut games, api.clone(), cb).await;
-            }
+        process_update(api.clone(), update).await;
+    }
 
-            _ => (),
+    // Make sure the pending changes are saved before exiting
+    persistence::flush().await;
+
+    Ok(())
+}
+
+/// Resolves on Ctrl+C or, on Unix, on SIGTERM sent by the platform on restarts.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => (),
+            _ = terminate.recv() => (),
         }
     }
 
-    Ok(())
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
 }
 
 pub(crate) fn crop_letters(s: &str, pos: usize) -> &str {
@@ -636,7 +1036,7 @@ pub(crate) fn crop_letters(s: &str, pos: usize) -> &str {
     }
 }
 
-fn escape_markdown(s: &str) -> String {
+pub(crate) fn escape_markdown(s: &str) -> String {
     let s = s.replace('*', "\\*");
     let s = s.replace('[', "\\[");
     let s = s.replace(']', "\\]");
@@ -647,3 +1047,594 @@ fn escape_markdown(s: &str) -> String {
     let s = s.replace('_', "\\_");
     s
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::bot;
+    use crate::bot::fake::{admin, member, Sent, TestChat};
+    use crate::commands::{deadline, duel, scoring};
+    use crate::storage::schema;
+    use crate::types::*;
+    use crate::voting::VotingPolicy;
+    use crate::GAMES;
+
+    /// Tricks of the members by name, along with the ids of the tricks they've proven.
+    fn tricks_and_proofs(game: &Game, user: &GameUser) -> (Vec<String>, Vec<usize>) {
+        let participant = &game.participants[user];
+        (
+            participant
+                .tricks
+                .iter()
+                .map(|trick| trick.name.clone())
+                .collect(),
+            participant
+                .proofs
+                .iter()
+                .flat_map(|proof| proof.tricks_proven.clone())
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn tricks_and_proofs_make_the_game_message() {
+        let chat = TestChat::new(-1001);
+        let (ann, bob) = (member(11, "Ann"), member(12, "Bob"));
+
+        chat.send(&ann, "/trick kickflip, heelflip").await;
+        chat.send(&bob, "/trick ollie").await;
+
+        let video = chat.video(&bob, "bob-1", None).await;
+        chat.reply(&bob, "/proof 1", video).await;
+        let video = chat.video(&ann, "ann-1", None).await;
+        chat.reply(&ann, "/proof 3", video).await;
+
+        let game = chat.game().await;
+        assert!(game.started());
+        assert_eq!(
+            tricks_and_proofs(&game, &ann),
+            (vec!["kickflip".to_owned(), "heelflip".to_owned()], vec![3])
+        );
+        assert_eq!(
+            tricks_and_proofs(&game, &bob),
+            (vec!["ollie".to_owned()], vec![1])
+        );
+
+        // The game message is sent once, then kept up to date and pinned
+        let game_message = game.game_message.expect("game message");
+        let posted = chat
+            .bot
+            .sent()
+            .into_iter()
+            .filter(|sent| sent.text.starts_with("=== Game of Skate"))
+            .collect::<Vec<_>>();
+        assert_eq!(posted.len(), 1);
+        assert_eq!(posted[0].message_id, game_message.id);
+        let last_edit = chat.bot.edited().pop().expect("game message edit");
+        assert_eq!(last_edit.message_id, game_message.id);
+        assert!(last_edit.text.contains("🛹 Bob \n3. ollie"));
+        assert!(chat
+            .bot
+            .pinned()
+            .iter()
+            .all(|pinned| pinned.message_id == game_message.id));
+    }
+
+    #[tokio::test]
+    async fn same_video_is_not_accepted_twice() {
+        let chat = TestChat::new(-1002);
+        let (ann, bob) = (member(11, "Ann"), member(12, "Bob"));
+
+        chat.send(&ann, "/trick kickflip").await;
+        chat.send(&bob, "/trick ollie").await;
+        let video = chat.video(&ann, "clip", None).await;
+        chat.reply(&ann, "/proof 2", video).await;
+        chat.reply(&bob, "/proof 1", video).await;
+        assert_eq!(
+            chat.last_sent().text,
+            "Это видео уже добавлено другим участником."
+        );
+
+        // Uploaded again, it's the same file
+        let video = chat.video(&bob, "clip", None).await;
+        chat.reply(&bob, "/proof 1", video).await;
+        assert_eq!(
+            chat.last_sent().text,
+            "Это видео уже использовано в пруфе участника Ann."
+        );
+
+        let game = chat.game().await;
+        assert_eq!(tricks_and_proofs(&game, &ann).1, vec![2]);
+        assert!(tricks_and_proofs(&game, &bob).1.is_empty());
+    }
+
+    #[tokio::test]
+    async fn captions_of_videos_are_commands() {
+        let chat = TestChat::new(-1008);
+        let (ann, bob) = (member(11, "Ann"), member(12, "Bob"));
+
+        chat.send(&ann, "/trick kickflip").await;
+        chat.send(&bob, "/trick ollie").await;
+        let first = chat.video(&bob, "bob-1", Some("/proof 1")).await;
+        let game = chat.game().await;
+        assert_eq!(tricks_and_proofs(&game, &bob).1, vec![1]);
+        assert_eq!(game.participants[&bob].proofs[0].msg.id, first);
+
+        let second = chat.video(&bob, "bob-2", Some("/reproof 1")).await;
+        let game = chat.game().await;
+        let proofs = &game.participants[&bob].proofs;
+        assert_eq!(proofs.len(), 1);
+        assert_eq!(proofs[0].msg.id, second);
+        assert_eq!(proofs[0].tricks_proven, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn renamed_tricks_keep_the_points_of_an_admin() {
+        let chat = TestChat::new(-1011);
+        let ann = member(11, "Ann");
+
+        chat.send(&ann, "/trick ollie, shove-it").await;
+        chat.send(&admin(), "/points 1 5").await;
+        chat.send(&ann, "/edit 1 kickflip").await;
+        chat.send(&ann, "/edit 2 heelflip").await;
+
+        let game = chat.game().await;
+        let trick = |id| game.trick_by_id(id).expect("trick");
+        assert_eq!((trick(1).name, trick(1).points), ("kickflip".to_owned(), 5));
+        // Without the admin it's scored by the new name
+        assert_eq!(trick(2).points, scoring::score("heelflip"));
+    }
+
+    #[tokio::test]
+    async fn replaced_videos_are_not_accepted_again() {
+        let chat = TestChat::new(-1012);
+        let (ann, bob) = (member(11, "Ann"), member(12, "Bob"));
+
+        chat.send(&ann, "/trick kickflip").await;
+        chat.send(&bob, "/trick ollie").await;
+        let video = chat.video(&bob, "bob-old", None).await;
+        chat.reply(&bob, "/proof 1", video).await;
+        chat.video(&bob, "bob-new", Some("/reproof 1")).await;
+
+        let video = chat.video(&ann, "bob-old", None).await;
+        chat.reply(&ann, "/proof 2", video).await;
+        assert_eq!(
+            chat.last_sent().text,
+            "Это видео уже использовано в пруфе участника Bob."
+        );
+        chat.video(&bob, "bob-old", Some("/reproof 1")).await;
+        assert_eq!(chat.last_sent().text, "Это видео уже добавлено.");
+
+        let game = chat.game().await;
+        assert!(tricks_and_proofs(&game, &ann).1.is_empty());
+        let proof = &game.participants[&bob].proofs[0];
+        assert_eq!(proof.file.as_ref().expect("file").unique_id, "bob-new");
+        assert_eq!(proof.replaced_files.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn unknown_trick_numbers_are_not_proven() {
+        let chat = TestChat::new(-1006);
+        let (ann, bob) = (member(11, "Ann"), member(12, "Bob"));
+
+        chat.send(&ann, "/trick kickflip").await;
+        chat.send(&bob, "/trick ollie").await;
+        let video = chat.video(&bob, "bob-1", None).await;
+        chat.reply(&bob, "/proof 1,99", video).await;
+
+        assert!(chat
+            .last_sent()
+            .text
+            .ends_with("Относится к трюкам:\n1. kickflip"));
+        let game = chat.game().await;
+        assert_eq!(tricks_and_proofs(&game, &bob).1, vec![1]);
+        assert!(!game.is_trick_proven(&bob, 99));
+    }
+
+    #[tokio::test]
+    async fn proofs_beyond_videos_are_photos_and_documents() {
+        let chat = TestChat::new(-1007);
+        let (ann, bob) = (member(11, "Ann"), member(12, "Bob"));
+
+        chat.send(&ann, "/trick kickflip, heelflip").await;
+        chat.send(&bob, "/trick ollie").await;
+        chat.send(&admin(), "/settings").await;
+        let settings = chat.last_sent().message_id;
+        chat.press(&admin(), settings, "settings,videos_only,toggle")
+            .await;
+        assert!(!chat.game().await.settings.videos_only);
+
+        let text = chat.send(&bob, "kickflip landed").await;
+        chat.reply(&bob, "/proof 1", text).await;
+        assert_eq!(
+            chat.last_sent().text,
+            "В качестве доказательства принимаются только видео, фото или файлы либо ответ на них."
+        );
+
+        let photo = chat.photo(&bob, "bob-photo").await;
+        chat.reply(&bob, "/proof 2", photo).await;
+        let document = chat.document(&bob, "bob-file", vec![]).await;
+        chat.reply(&bob, "/proof 1", document).await;
+
+        let game = chat.game().await;
+        let proofs = &game.participants[&bob].proofs;
+        assert_eq!(
+            proofs
+                .iter()
+                .map(|proof| (proof.msg.id, proof.tricks_proven.clone()))
+                .collect::<Vec<_>>(),
+            vec![(photo, vec![2]), (document, vec![1])]
+        );
+
+        // The same photo posted again is the same file
+        let photo = chat.photo(&bob, "bob-photo").await;
+        chat.reply(&bob, "/proof 3", photo).await;
+        assert_eq!(chat.last_sent().text, "Это видео уже добавлено.");
+        assert_eq!(chat.game().await.participants[&bob].proofs.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn only_participants_vote_on_a_challenge() {
+        let chat = TestChat::new(-1003);
+        let (ann, bob, carl) = (member(11, "Ann"), member(12, "Bob"), member(13, "Carl"));
+
+        chat.send(&ann, "/trick kickflip").await;
+        chat.send(&bob, "/trick ollie").await;
+        let video = chat.video(&bob, "bob-1", None).await;
+        chat.reply(&bob, "/proof 1", video).await;
+        chat.reply(&ann, "/challenge", video).await;
+
+        let poll = chat.last_sent();
+        assert_eq!(poll.buttons.len(), 2);
+        chat.press(&carl, poll.message_id, &poll.buttons[1]).await;
+        let answer = chat.bot.answers().pop().expect("answer");
+        assert_eq!(answer.text, "Голосовать могут только участники игры.");
+
+        let game = chat.game().await;
+        let challenge = game.proof_challenges.values().next().expect("challenge");
+        assert_eq!(challenge.poll_msg.id, poll.message_id);
+        assert_eq!((challenge.num_yes(), challenge.num_no()), (0, 0));
+    }
+
+    /// Bob's proof of Ann's trick, challenged by Ann, in a game of four where Carl has
+    /// a proof as well. Returns the proof and the poll.
+    async fn challenged_proof(chat: &TestChat) -> (i64, Sent) {
+        let (ann, bob) = (member(11, "Ann"), member(12, "Bob"));
+        let (carl, dan) = (member(13, "Carl"), member(14, "Dan"));
+
+        chat.send(&ann, "/trick kickflip").await;
+        chat.send(&bob, "/trick ollie").await;
+        chat.send(&carl, "/trick heelflip").await;
+        chat.send(&dan, "/trick shove-it").await;
+        let video = chat.video(&carl, "carl-1", None).await;
+        chat.reply(&carl, "/proof 1", video).await;
+        let proof = chat.video(&bob, "bob-1", None).await;
+        chat.reply(&bob, "/proof 1", proof).await;
+        chat.reply(&ann, "/challenge", proof).await;
+
+        (proof, chat.last_sent())
+    }
+
+    #[tokio::test]
+    async fn deciding_vote_rejects_the_challenged_proof() {
+        let chat = TestChat::new(-1013);
+        let (ann, bob, carl) = (member(11, "Ann"), member(12, "Bob"), member(13, "Carl"));
+        let (proof, poll) = challenged_proof(&chat).await;
+        let (yes, no) = (&poll.buttons[0], &poll.buttons[1]);
+
+        // Three of the four decide
+        chat.press(&bob, poll.message_id, yes).await;
+        chat.press(&ann, poll.message_id, no).await;
+        let game = chat.game().await;
+        let challenge = game.proof_challenges.values().next().expect("challenge");
+        assert_eq!((challenge.num_yes(), challenge.num_no()), (1, 1));
+        assert_eq!(tricks_and_proofs(&game, &bob).1, vec![1]);
+
+        chat.press(&carl, poll.message_id, no).await;
+        let game = chat.game().await;
+        assert!(game.proof_challenges.is_empty());
+        assert!(game.participants[&bob].proofs.is_empty());
+        assert_eq!(tricks_and_proofs(&game, &carl).1, vec![1]);
+
+        let removed = chat.last_sent();
+        assert_eq!(removed.text, "Это доказательство удалено.");
+        assert_eq!(removed.reply_to, Some(proof));
+        let verdict = chat
+            .bot
+            .edited()
+            .into_iter()
+            .rev()
+            .find(|edit| edit.message_id == poll.message_id)
+            .expect("verdict");
+        assert!(verdict.text.contains("ПЕРЕДЕЛАТЬ"));
+    }
+
+    #[tokio::test]
+    async fn deciding_vote_keeps_the_challenged_proof() {
+        let chat = TestChat::new(-1014);
+        let (ann, bob, carl) = (member(11, "Ann"), member(12, "Bob"), member(13, "Carl"));
+        let (_, poll) = challenged_proof(&chat).await;
+        let (yes, no) = (&poll.buttons[0], &poll.buttons[1]);
+
+        chat.press(&ann, poll.message_id, no).await;
+        chat.press(&bob, poll.message_id, yes).await;
+        chat.press(&carl, poll.message_id, yes).await;
+
+        let game = chat.game().await;
+        assert!(game.proof_challenges.is_empty());
+        assert_eq!(tricks_and_proofs(&game, &bob).1, vec![1]);
+        assert_eq!(tricks_and_proofs(&game, &carl).1, vec![1]);
+        assert!(chat
+            .bot
+            .sent()
+            .iter()
+            .all(|sent| sent.text != "Это доказательство удалено."));
+    }
+
+    #[tokio::test]
+    async fn votes_on_a_challenge_are_changed_and_taken_back() {
+        let chat = TestChat::new(-1015);
+        let carl = member(13, "Carl");
+        let (_, poll) = challenged_proof(&chat).await;
+        let (yes, no) = (&poll.buttons[0], &poll.buttons[1]);
+        let votes = || async {
+            let game = chat.game().await;
+            let challenge = game.proof_challenges.values().next().expect("challenge");
+            (challenge.num_yes(), challenge.num_no())
+        };
+        let answer = || chat.bot.answers().pop().expect("answer").text;
+
+        chat.press(&carl, poll.message_id, yes).await;
+        assert_eq!(answer(), "Твой голос принят.");
+        assert_eq!(votes().await, (1, 0));
+
+        chat.press(&carl, poll.message_id, no).await;
+        assert_eq!(answer(), "Твой голос изменен.");
+        assert_eq!(votes().await, (0, 1));
+
+        chat.press(&carl, poll.message_id, no).await;
+        assert_eq!(answer(), "Твой голос отменен.");
+        assert_eq!(votes().await, (0, 0));
+
+        chat.press(&carl, poll.message_id, yes).await;
+        chat.press(&carl, poll.message_id, yes).await;
+        assert_eq!(answer(), "Твой голос отменен.");
+        assert_eq!(votes().await, (0, 0));
+    }
+
+    #[tokio::test]
+    async fn admin_veto_from_outside_the_game_is_not_counted() {
+        let chat = TestChat::new(-1019);
+        let bob = member(12, "Bob");
+        let (_, poll) = challenged_proof(&chat).await;
+        GAMES
+            .lock()
+            .await
+            .get_mut(&chat.id.to_string())
+            .expect("game")
+            .settings
+            .voting = VotingPolicy::AdminVeto;
+
+        chat.press(&admin(), poll.message_id, &poll.buttons[1])
+            .await;
+
+        let game = chat.game().await;
+        assert!(game.proof_challenges.is_empty());
+        assert!(game.participants[&bob].proofs.is_empty());
+        let verdict = chat
+            .bot
+            .edited()
+            .into_iter()
+            .rev()
+            .find(|edit| edit.message_id == poll.message_id)
+            .expect("verdict");
+        assert!(verdict.text.contains("0 👍, 0 👎"));
+    }
+
+    #[tokio::test]
+    async fn votes_of_a_leaving_participant_are_taken_back() {
+        let chat = TestChat::new(-1020);
+        let (ann, bob, carl) = (member(11, "Ann"), member(12, "Bob"), member(13, "Carl"));
+        let (_, poll) = challenged_proof(&chat).await;
+        let (yes, no) = (&poll.buttons[0], &poll.buttons[1]);
+
+        chat.press(&bob, poll.message_id, yes).await;
+        chat.press(&carl, poll.message_id, no).await;
+        chat.send(&carl, "/leave").await;
+        let game = chat.game().await;
+        let challenge = game.proof_challenges.values().next().expect("challenge");
+        assert_eq!((challenge.num_yes(), challenge.num_no()), (1, 0));
+
+        // Two of the three left decide, and the tie keeps the proof
+        chat.press(&ann, poll.message_id, no).await;
+        let game = chat.game().await;
+        assert!(game.proof_challenges.is_empty());
+        assert_eq!(tricks_and_proofs(&game, &bob).1, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn leaving_participant_lets_the_votes_cast_decide() {
+        let chat = TestChat::new(-1021);
+        let (ann, bob, carl) = (member(11, "Ann"), member(12, "Bob"), member(13, "Carl"));
+        let (_, poll) = challenged_proof(&chat).await;
+        let no = &poll.buttons[1];
+
+        chat.press(&ann, poll.message_id, no).await;
+        chat.press(&carl, poll.message_id, no).await;
+        assert_eq!(chat.game().await.proof_challenges.len(), 1);
+
+        chat.send(&member(14, "Dan"), "/leave").await;
+        let game = chat.game().await;
+        assert!(game.proof_challenges.is_empty());
+        assert!(game.participants[&bob].proofs.is_empty());
+        let verdict = chat
+            .bot
+            .edited()
+            .into_iter()
+            .rev()
+            .find(|edit| edit.message_id == poll.message_id)
+            .expect("verdict");
+        assert!(verdict.text.contains("ПЕРЕДЕЛАТЬ"));
+    }
+
+    #[tokio::test]
+    async fn deadline_decides_the_open_challenges() {
+        let chat = TestChat::new(-1016);
+        let (ann, bob) = (member(11, "Ann"), member(12, "Bob"));
+        let (proof, poll) = challenged_proof(&chat).await;
+        chat.press(&ann, poll.message_id, &poll.buttons[1]).await;
+
+        let mut games = GAMES.lock().await;
+        let game = games.get_mut(&chat.id.to_string()).expect("game");
+        game.deadline = Some(1);
+        deadline::check_deadline(&chat.bot, &chat.id.to_string(), game)
+            .await
+            .unwrap();
+
+        assert!(game.finished());
+        assert!(game.proof_challenges.is_empty());
+        assert!(game.participants[&bob].proofs.is_empty());
+        assert!(
+            chat.bot
+                .sent()
+                .iter()
+                .any(|sent| sent.text == "Это доказательство удалено."
+                    && sent.reply_to == Some(proof))
+        );
+        let verdict = chat
+            .bot
+            .edited()
+            .into_iter()
+            .rev()
+            .find(|edit| edit.message_id == poll.message_id)
+            .expect("verdict");
+        assert!(verdict.text.contains("Время вышло"));
+    }
+
+    /// Starts a duel of Ann and Bob in the chat, where Carl and Dan play the game, and
+    /// has Carl dispute Bob's repeat. Returns the poll.
+    async fn disputed_repeat(chat: &TestChat) -> Sent {
+        let (ann, bob) = (member(11, "Ann"), member(12, "Bob"));
+        let (carl, dan) = (member(13, "Carl"), member(14, "Dan"));
+
+        chat.send(&carl, "/trick kickflip").await;
+        chat.send(&dan, "/trick ollie").await;
+        let hello = chat.send(&bob, "Привет").await;
+        chat.reply(&ann, "/duel", hello).await;
+        chat.video(&ann, "ann-set", Some("/duel set kickflip"))
+            .await;
+        let landed = chat.video(&bob, "bob-land", Some("/duel land")).await;
+        chat.reply(&carl, "/challenge", landed).await;
+
+        chat.last_sent()
+    }
+
+    #[tokio::test]
+    async fn repeats_are_voted_on_like_proofs() {
+        let chat = TestChat::new(-1009);
+        let (ann, bob) = (member(11, "Ann"), member(12, "Bob"));
+        let (carl, dan, eve) = (member(13, "Carl"), member(14, "Dan"), member(15, "Eve"));
+        let poll = disputed_repeat(&chat).await;
+        let (yes, no) = (&poll.buttons[0], &poll.buttons[1]);
+        let answer = || chat.bot.answers().pop().expect("answer").text;
+
+        // Neither player has a say, whoever repeated the trick
+        chat.press(&bob, poll.message_id, yes).await;
+        assert_eq!(answer(), "Игроки дуэли за повтор не голосуют.");
+        chat.press(&ann, poll.message_id, no).await;
+        assert_eq!(answer(), "Игроки дуэли за повтор не голосуют.");
+        chat.press(&eve, poll.message_id, no).await;
+        assert_eq!(answer(), "Голосовать могут только участники игры.");
+
+        chat.press(&carl, poll.message_id, yes).await;
+        assert_eq!(answer(), "Твой голос принят.");
+        chat.press(&carl, poll.message_id, no).await;
+        assert_eq!(answer(), "Твой голос изменен.");
+        let game = chat.game().await;
+        let dispute = game.duel.unwrap().dispute.expect("dispute");
+        assert_eq!((dispute.num_yes(), dispute.num_no()), (0, 1));
+
+        // Both of the two who may vote decide
+        chat.press(&dan, poll.message_id, no).await;
+        let duel = chat.game().await.duel.expect("duel");
+        assert!(duel.dispute.is_none());
+        assert_eq!(duel.players[1].letters, 1);
+        let verdict = chat
+            .bot
+            .edited()
+            .into_iter()
+            .rev()
+            .find(|edit| edit.message_id == poll.message_id)
+            .expect("verdict");
+        assert!(verdict.text.contains("Вердикт: ❌ НЕ ЗАСЧИТАНО"));
+    }
+
+    #[tokio::test]
+    async fn repeats_count_once_the_vote_is_out_of_time() {
+        let chat = TestChat::new(-1010);
+        let poll = disputed_repeat(&chat).await;
+        chat.press(&member(13, "Carl"), poll.message_id, &poll.buttons[0])
+            .await;
+
+        let mut games = GAMES.lock().await;
+        let game = games.get_mut(&chat.id.to_string()).expect("game");
+        let dispute = game.duel.as_mut().and_then(|duel| duel.dispute.as_mut());
+        dispute.expect("dispute").opened_at = 0;
+        let mut outgoing = vec![];
+        assert!(duel::close_expired_dispute(
+            &chat.id.to_string(),
+            game,
+            &mut outgoing
+        ));
+        bot::send_all(&chat.bot, outgoing).await.unwrap();
+
+        let duel = game.duel.as_ref().expect("duel");
+        assert!(duel.dispute.is_none());
+        assert_eq!(duel.players[1].letters, 0);
+        let verdict = chat
+            .bot
+            .edited()
+            .into_iter()
+            .rev()
+            .find(|edit| edit.message_id == poll.message_id)
+            .expect("verdict");
+        assert!(verdict.text.contains("Вердикт: Время вышло: ✅ ЗАСЧИТАНО"));
+    }
+
+    #[tokio::test]
+    async fn import_downloads_the_exported_game() {
+        let (source, chat) = (TestChat::new(-1004), TestChat::new(-1005));
+        let ann = member(11, "Ann");
+
+        source.send(&ann, "/trick kickflip").await;
+        let body = schema::game_to_string("-1004", &source.game().await).unwrap();
+        let file = chat.document(&ann, "export", body.into_bytes()).await;
+        chat.reply(&admin(), "/import", file).await;
+
+        assert_eq!(chat.last_sent().text, "Игра загружена в этот чат.");
+        let game = chat.game().await;
+        assert_eq!(
+            tricks_and_proofs(&game, &ann).0,
+            vec!["kickflip".to_owned()]
+        );
+        assert_eq!(
+            game.game_message.map(|msg| msg.chat_id),
+            Some(chat.id),
+            "the game message is posted in the new chat"
+        );
+    }
+
+    #[tokio::test]
+    async fn import_leaves_the_vote_on_a_repeat_behind() {
+        let (source, chat) = (TestChat::new(-1017), TestChat::new(-1018));
+        disputed_repeat(&source).await;
+
+        let body = schema::game_to_string("-1017", &source.game().await).unwrap();
+        let file = chat.document(&admin(), "export", body.into_bytes()).await;
+        chat.reply(&admin(), "/import", file).await;
+
+        assert_eq!(chat.last_sent().text, "Игра загружена в этот чат.");
+        let duel = chat.game().await.duel.expect("duel");
+        assert!(duel.dispute.is_none() && duel.landed_trick.is_none());
+        assert_eq!(duel.players[1].letters, 0);
+    }
+}
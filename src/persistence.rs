@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use indexmap::IndexSet;
+use telegram_bot::*;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+use crate::bot::Bot;
+use crate::storage::{self, StorageError};
+use crate::GAMES;
+
+/// How long changes are collected before being saved together, unless set with
+/// the `SAVE_DEBOUNCE_MS` environment variable.
+const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(2);
+
+enum Command {
+    /// The game of the chat was changed and has to be saved.
+    Dirty(String),
+    /// Save everything pending right away and report back.
+    Flush(oneshot::Sender<()>),
+}
+
+lazy_static! {
+    static ref SENDER: std::sync::Mutex<Option<UnboundedSender<Command>>> = Default::default();
+}
+
+/// Starts the background task that saves the games of changed chats.
+pub(crate) fn spawn<B: Bot>(api: B) {
+    let debounce = std::env::var("SAVE_DEBOUNCE_MS")
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_DEBOUNCE);
+
+    let (sender, receiver) = unbounded_channel();
+    *SENDER.lock().unwrap() = Some(sender);
+    tokio::spawn(run(api, receiver, debounce));
+}
+
+/// Schedules the game of the chat to be saved.
+pub(crate) fn mark_dirty(chat: &MessageChat) {
+    mark_chat_dirty(chat.id().to_string());
+}
+
+/// Schedules the game stored under the chat id to be saved, or removed if it's gone.
+pub(crate) fn mark_chat_dirty(chat_id: String) {
+    if !send(Command::Dirty(chat_id.clone())) {
+        eprintln!(
+            "Persistence is not running, chat {} won't be saved",
+            chat_id
+        );
+    }
+}
+
+/// Saves all the pending changes and waits until that's done.
+pub(crate) async fn flush() {
+    let (sender, receiver) = oneshot::channel();
+    if send(Command::Flush(sender)) {
+        let _ = receiver.await;
+    }
+}
+
+fn send(command: Command) -> bool {
+    match SENDER.lock().unwrap().as_ref() {
+        Some(sender) => sender.send(command).is_ok(),
+        None => false,
+    }
+}
+
+async fn run<B: Bot>(api: B, mut receiver: UnboundedReceiver<Command>, debounce: Duration) {
+    let mut dirty = IndexSet::new();
+    let mut flushed = vec![];
+
+    while let Some(command) = receiver.recv().await {
+        let deadline = Instant::now() + debounce;
+        let mut command = Some(command);
+
+        // Coalesce the changes until the window closes or a flush is requested
+        while let Some(cmd) = command.take() {
+            match cmd {
+                Command::Dirty(chat_id) => {
+                    dirty.insert(chat_id);
+                }
+                Command::Flush(done) => flushed.push(done),
+            }
+
+            if flushed.is_empty() {
+                command = tokio::time::timeout_at(deadline, receiver.recv())
+                    .await
+                    .ok()
+                    .flatten();
+            }
+        }
+
+        save(&api, dirty.drain(..).collect()).await;
+        for done in flushed.drain(..) {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// Saves the games of the chats, retrying with backoff and letting the chats know
+/// when their state could not be saved right away.
+async fn save<B: Bot>(api: &B, chat_ids: Vec<String>) {
+    if chat_ids.is_empty() {
+        return;
+    }
+
+    // Only copy the games under the lock so that other chats aren't blocked by the upload
+    let games = GAMES.lock().await.clone();
+    let mut delays = storage::RETRY_DELAYS.iter();
+    let mut notified = false;
+
+    loop {
+        match storage::save_chats(chat_ids.clone(), games.clone()).await {
+            Ok(()) => return,
+
+            Err(StorageError::NotLoaded) => {
+                eprintln!(
+                    "Not saving chats {:?}: saved games were not loaded",
+                    chat_ids
+                );
+                notify(
+                    api,
+                    &chat_ids,
+                    "⚠️ Сохраненные игры не загрузились, поэтому изменения не будут сохранены.",
+                )
+                .await;
+                return;
+            }
+
+            Err(e) => match delays.next() {
+                Some(delay) => {
+                    eprintln!("Game save error, retrying in {:?}: {}", delay, e);
+                    if !notified {
+                        notify(
+                            api,
+                            &chat_ids,
+                            "⚠️ Не удалось сохранить состояние игры, пробую еще раз...",
+                        )
+                        .await;
+                        notified = true;
+                    }
+                    tokio::time::delay_for(*delay).await;
+                }
+
+                None => {
+                    eprintln!("Game save error, giving up: {}", e);
+                    notify(
+                        api,
+                        &chat_ids,
+                        "❌ Состояние игры сохранить так и не удалось.",
+                    )
+                    .await;
+                    return;
+                }
+            },
+        }
+    }
+}
+
+async fn notify<B: Bot>(api: &B, chat_ids: &[String], text: &str) {
+    for chat_id in chat_ids {
+        if let Ok(chat_id) = chat_id.parse::<i64>() {
+            // Nothing else to do if even the notice can't be sent
+            let _ = api.send(ChatId::new(chat_id).text(text)).await;
+        }
+    }
+}
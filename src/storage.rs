@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::types::Game;
+
+pub(crate) mod dropbox;
+pub(crate) mod file;
+pub(crate) mod schema;
+pub(crate) mod sqlite;
+
+lazy_static! {
+    /// Opened on first use. Stays empty while opening fails, so the next use retries.
+    static ref STORE: Mutex<Option<Arc<dyn GameStore>>> = Mutex::new(None);
+}
+
+/// Set once the games were loaded. Until then nothing is saved, so that a failed
+/// load can't end up overwriting the stored games with empty ones.
+static LOADED: AtomicBool = AtomicBool::new(false);
+
+/// Id of the latest snapshot taken by this process.
+static LAST_SNAPSHOT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Delays before retrying a failed storage operation.
+pub(crate) const RETRY_DELAYS: &[Duration] = &[
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+    Duration::from_secs(15),
+    Duration::from_secs(60),
+];
+
+#[derive(Debug)]
+pub(crate) enum StorageError {
+    /// Dropbox request failed or was rejected.
+    Dropbox(String),
+    /// Remote file kept changing under us while saving.
+    Conflict,
+    Io(std::io::Error),
+    Yaml(serde_yaml::Error),
+    Sqlite(rusqlite::Error),
+    /// Saved games were written by a newer version of the bot.
+    UnsupportedVersion(u64),
+    /// Saving is refused because the stored games were never loaded.
+    NotLoaded,
+    /// `STORAGE` names no known backend.
+    UnknownBackend(String),
+    /// Environment variable the backend needs is not set.
+    MissingVar(&'static str),
+    /// Blocking storage task panicked or was cancelled.
+    Task(tokio::task::JoinError),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Dropbox(e) => write!(f, "Dropbox error: {}", e),
+            StorageError::Conflict => write!(f, "concurrent modification conflict"),
+            StorageError::Io(e) => write!(f, "I/O error: {}", e),
+            StorageError::Yaml(e) => write!(f, "YAML error: {}", e),
+            StorageError::Sqlite(e) => write!(f, "SQLite error: {}", e),
+            StorageError::UnsupportedVersion(version) => {
+                write!(f, "unsupported saved games version {}", version)
+            }
+            StorageError::NotLoaded => write!(f, "games were not loaded, refusing to save"),
+            StorageError::UnknownBackend(name) => write!(f, "unknown storage backend: {}", name),
+            StorageError::MissingVar(name) => write!(f, "{} is not set", name),
+            StorageError::Task(e) => write!(f, "storage task failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<std::io::Error> for StorageError {
+    fn from(e: std::io::Error) -> Self {
+        StorageError::Io(e)
+    }
+}
+
+impl From<serde_yaml::Error> for StorageError {
+    fn from(e: serde_yaml::Error) -> Self {
+        StorageError::Yaml(e)
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError::Sqlite(e)
+    }
+}
+
+impl From<tokio::task::JoinError> for StorageError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        StorageError::Task(e)
+    }
+}
+
+pub(crate) type Result<T> = std::result::Result<T, StorageError>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SnapshotReason {
+    Reset,
+    Restore,
+    Daily,
+    Import,
+}
+
+impl SnapshotReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SnapshotReason::Reset => "reset",
+            SnapshotReason::Restore => "restore",
+            SnapshotReason::Daily => "daily",
+            SnapshotReason::Import => "import",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "reset" => Some(SnapshotReason::Reset),
+            "restore" => Some(SnapshotReason::Restore),
+            "daily" => Some(SnapshotReason::Daily),
+            "import" => Some(SnapshotReason::Import),
+            _ => None,
+        }
+    }
+
+    /// How many snapshots of the reason a chat keeps, the older ones are deleted.
+    pub fn kept(self) -> usize {
+        match self {
+            SnapshotReason::Daily => 7,
+            _ => 10,
+        }
+    }
+}
+
+/// Copy of a chat's game taken at some point in time.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SnapshotInfo {
+    /// Unix time the snapshot was taken at, also used as its id. A snapshot taken in the
+    /// same second as the one before gets the next second, so that the ids stay unique.
+    pub id: u64,
+    pub reason: SnapshotReason,
+}
+
+impl SnapshotInfo {
+    /// Name of the file the snapshot is kept in by file-based stores.
+    pub fn file_name(&self) -> String {
+        format!("{}-{}.yaml", self.id, self.reason.as_str())
+    }
+
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        let name = name.strip_suffix(".yaml")?;
+        let mut parts = name.splitn(2, '-');
+        let id = parts.next()?.parse().ok()?;
+        let reason = SnapshotReason::parse(parts.next()?)?;
+        Some(SnapshotInfo { id, reason })
+    }
+}
+
+/// Persistent storage for the games of all chats, keyed by chat id.
+pub(crate) trait GameStore: Send + Sync {
+    /// Loads all the games. A store that has nothing saved yet returns no games.
+    fn load(&self) -> Result<HashMap<String, Game>>;
+
+    fn save(&self, games: &HashMap<String, Game>) -> Result<()>;
+
+    /// Persists the games of the given chats, removing those missing from `games`.
+    /// Stores that keep everything in one document may simply rewrite all the games.
+    fn save_chats(&self, _chat_ids: &[String], games: &HashMap<String, Game>) -> Result<()> {
+        self.save(games)
+    }
+
+    fn save_snapshot(&self, chat_id: &str, snapshot: &SnapshotInfo, game: &Game) -> Result<()>;
+
+    /// Lists the snapshots of the chat, oldest first.
+    fn list_snapshots(&self, chat_id: &str) -> Result<Vec<SnapshotInfo>>;
+
+    fn load_snapshot(&self, chat_id: &str, id: u64) -> Result<Option<Game>>;
+
+    fn delete_snapshot(&self, chat_id: &str, snapshot: &SnapshotInfo) -> Result<()>;
+
+    /// Moves the snapshots of a group to the id it got as a supergroup, along with
+    /// the messages of their games.
+    fn move_snapshots(&self, from_chat_id: &str, to_chat_id: i64) -> Result<()> {
+        for snapshot in self.list_snapshots(from_chat_id)? {
+            if let Some(mut game) = self.load_snapshot(from_chat_id, snapshot.id)? {
+                game.move_to_chat(to_chat_id);
+                self.save_snapshot(&to_chat_id.to_string(), &snapshot, &game)?;
+            }
+            self.delete_snapshot(from_chat_id, &snapshot)?;
+        }
+        Ok(())
+    }
+}
+
+/// Selects the storage backend with the `STORAGE` environment variable
+/// (`dropbox`, `file` or `sqlite`). Without it, Dropbox is used when `DROPBOX_OAUTH_TOKEN`
+/// is set and a local YAML file otherwise.
+fn from_env() -> Result<Box<dyn GameStore>> {
+    let backend = std::env::var("STORAGE").unwrap_or_else(|_| {
+        if std::env::var("DROPBOX_OAUTH_TOKEN").is_ok() {
+            "dropbox".to_owned()
+        } else {
+            "file".to_owned()
+        }
+    });
+
+    Ok(match backend.to_lowercase().as_str() {
+        "dropbox" => Box::new(dropbox::DropboxStore::from_env()?),
+        "file" => Box::new(file::FileStore::from_env()),
+        "sqlite" => Box::new(sqlite::SqliteStore::from_env()?),
+        other => return Err(StorageError::UnknownBackend(other.to_owned())),
+    })
+}
+
+/// The store selected by the environment, opening it if that wasn't done yet.
+fn store() -> Result<Arc<dyn GameStore>> {
+    let mut store = STORE.lock().unwrap();
+    if store.is_none() {
+        *store = Some(from_env()?.into());
+    }
+    Ok(store.as_ref().unwrap().clone())
+}
+
+/// Loads the games, retrying with backoff. Saving is only enabled after this succeeds.
+pub(crate) async fn load_games() -> Result<HashMap<String, Game>> {
+    let mut delays = RETRY_DELAYS.iter();
+    loop {
+        match tokio::task::spawn_blocking(|| store()?.load()).await? {
+            Ok(games) => {
+                LOADED.store(true, Ordering::SeqCst);
+                return Ok(games);
+            }
+
+            Err(e) => match delays.next() {
+                Some(delay) => {
+                    eprintln!("Games load error, retrying in {:?}: {}", delay, e);
+                    tokio::time::delay_for(*delay).await;
+                }
+                None => return Err(e),
+            },
+        }
+    }
+}
+
+pub(crate) async fn save_chats(chat_ids: Vec<String>, games: HashMap<String, Game>) -> Result<()> {
+    if !LOADED.load(Ordering::SeqCst) {
+        return Err(StorageError::NotLoaded);
+    }
+
+    tokio::task::spawn_blocking(move || store()?.save_chats(&chat_ids, &games)).await?
+}
+
+/// Keeps a copy of the chat's game that can be restored later.
+pub(crate) async fn save_snapshot(
+    chat_id: &str,
+    game: &Game,
+    reason: SnapshotReason,
+) -> Result<SnapshotInfo> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let snapshot = SnapshotInfo {
+        id: next_snapshot_id(now),
+        reason,
+    };
+
+    let chat_id = chat_id.to_owned();
+    let game = game.clone();
+    let info = snapshot.clone();
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        let store = store()?;
+        store.save_snapshot(&chat_id, &info, &game)?;
+
+        // The snapshot is kept even if the old ones couldn't be deleted
+        if let Err(e) = prune_snapshots(&*store, &chat_id) {
+            eprintln!("Snapshots prune error for chat {}: {}", chat_id, e);
+        }
+        Ok(())
+    })
+    .await??;
+
+    Ok(snapshot)
+}
+
+fn next_snapshot_id(now: u64) -> u64 {
+    let mut id = now;
+    let _ = LAST_SNAPSHOT_ID.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+        id = now.max(last + 1);
+        Some(id)
+    });
+    id
+}
+
+fn prune_snapshots(store: &dyn GameStore, chat_id: &str) -> Result<()> {
+    for snapshot in expired_snapshots(&store.list_snapshots(chat_id)?) {
+        store.delete_snapshot(chat_id, snapshot)?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn move_snapshots(from_chat_id: &str, to_chat_id: i64) -> Result<()> {
+    let from_chat_id = from_chat_id.to_owned();
+    tokio::task::spawn_blocking(move || store()?.move_snapshots(&from_chat_id, to_chat_id)).await?
+}
+
+/// Snapshots beyond the number kept for their reason, given the snapshots oldest first.
+fn expired_snapshots(snapshots: &[SnapshotInfo]) -> Vec<&SnapshotInfo> {
+    let mut counts = HashMap::new();
+    let mut expired = snapshots
+        .iter()
+        .rev()
+        .filter(|snapshot| {
+            let count = counts.entry(snapshot.reason.as_str()).or_insert(0);
+            *count += 1;
+            *count > snapshot.reason.kept()
+        })
+        .collect::<Vec<_>>();
+    expired.reverse();
+    expired
+}
+
+pub(crate) async fn list_snapshots(chat_id: &str) -> Result<Vec<SnapshotInfo>> {
+    let chat_id = chat_id.to_owned();
+    tokio::task::spawn_blocking(move || store()?.list_snapshots(&chat_id)).await?
+}
+
+pub(crate) async fn load_snapshot(chat_id: &str, id: u64) -> Result<Option<Game>> {
+    let chat_id = chat_id.to_owned();
+    tokio::task::spawn_blocking(move || store()?.load_snapshot(&chat_id, id)).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(id: u64, reason: SnapshotReason) -> SnapshotInfo {
+        SnapshotInfo { id, reason }
+    }
+
+    #[test]
+    fn snapshot_ids_are_unique() {
+        let first = next_snapshot_id(1_000_000);
+        let second = next_snapshot_id(1_000_000);
+        assert!(second > first);
+        assert!(next_snapshot_id(first) > second);
+    }
+
+    #[test]
+    fn expired_snapshots_are_the_oldest_of_each_reason() {
+        let mut snapshots = (0..10)
+            .map(|id| snapshot(id, SnapshotReason::Daily))
+            .collect::<Vec<_>>();
+        snapshots.push(snapshot(10, SnapshotReason::Reset));
+
+        let expired = expired_snapshots(&snapshots);
+        assert_eq!(
+            expired
+                .iter()
+                .map(|snapshot| snapshot.id)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        assert!(expired_snapshots(&snapshots[..7]).is_empty());
+    }
+
+    #[test]
+    fn snapshot_file_names_read_back() {
+        let snapshot = snapshot(1600000000, SnapshotReason::Restore);
+        assert_eq!(snapshot.file_name(), "1600000000-restore.yaml");
+        assert_eq!(
+            SnapshotInfo::from_file_name(&snapshot.file_name()),
+            Some(snapshot)
+        );
+        assert_eq!(SnapshotInfo::from_file_name("1600000000-other.yaml"), None);
+    }
+}
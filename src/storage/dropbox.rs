@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::Mutex;
+
+use dropbox_sdk::files::{
+    delete_v2, download, list_folder, list_folder_continue, upload, CommitInfo, DeleteArg,
+    DeleteError, DownloadArg, DownloadError, FileMetadata, ListFolderArg, ListFolderContinueArg,
+    ListFolderError, LookupError, Metadata, UploadError, UploadWriteFailed, WriteError, WriteMode,
+};
+use dropbox_sdk::HyperClient;
+
+use crate::storage::{schema, GameStore, Result, SnapshotInfo, StorageError};
+use crate::types::Game;
+
+const FILE_NAME: &str = "/Apps/skate-tg-bot/games.yaml";
+const SNAPSHOTS_DIR: &str = "/Apps/skate-tg-bot/snapshots";
+
+/// How many times a conflicting upload is re-merged before giving up.
+const MAX_CONFLICT_RETRIES: usize = 5;
+
+pub(crate) struct DropboxStore {
+    token: String,
+    /// Revision of the file this instance has last seen, `None` if there is no file yet.
+    rev: Mutex<Option<String>>,
+    /// Games of that revision. Saves put only the changed chats over them, so the chats
+    /// written by another instance are kept even though this one never loaded them.
+    remote: Mutex<HashMap<String, Game>>,
+}
+
+impl DropboxStore {
+    pub fn from_env() -> Result<Self> {
+        let token = std::env::var("DROPBOX_OAUTH_TOKEN")
+            .map_err(|_| StorageError::MissingVar("DROPBOX_OAUTH_TOKEN"))?;
+
+        Ok(DropboxStore {
+            token,
+            rev: Mutex::new(None),
+            remote: Mutex::new(Default::default()),
+        })
+    }
+
+    fn client(&self) -> HyperClient {
+        HyperClient::new(self.token.clone())
+    }
+
+    fn download(&self, client: &HyperClient) -> Result<HashMap<String, Game>> {
+        let (rev, games) = match download_file(client, FILE_NAME)? {
+            Some((metadata, Some(body))) => (Some(metadata.rev), schema::from_reader(body)?),
+            Some((metadata, None)) => (Some(metadata.rev), Default::default()),
+
+            // Nothing was saved yet
+            None => (None, Default::default()),
+        };
+
+        *self.rev.lock().unwrap() = rev;
+        *self.remote.lock().unwrap() = games.clone();
+        Ok(games)
+    }
+
+    /// Puts the given `chats` over the remote games we know and uploads them, only if the
+    /// remote file is still at the revision we know. When another instance has written
+    /// in between, the remote games are downloaded again before the next attempt.
+    fn upload_merged(&self, games: &HashMap<String, Game>, chats: &[String]) -> Result<()> {
+        let client = self.client();
+        let mut merged = self.remote.lock().unwrap().clone();
+        put_chats(&mut merged, games, chats);
+
+        for _ in 0..MAX_CONFLICT_RETRIES {
+            let rev = self.rev.lock().unwrap().clone();
+            let mode = match rev {
+                Some(rev) => WriteMode::Update(rev),
+                None => WriteMode::Add,
+            };
+
+            let body = schema::to_string(&merged)?;
+            match upload_file(&client, FILE_NAME, mode, body.as_bytes())? {
+                Ok(metadata) => {
+                    *self.rev.lock().unwrap() = Some(metadata.rev);
+                    *self.remote.lock().unwrap() = merged;
+                    return Ok(());
+                }
+
+                Err(UploadError::Path(UploadWriteFailed {
+                    reason: WriteError::Conflict(_),
+                    ..
+                })) => {
+                    eprintln!("Dropbox upload conflict, merging with the remote games");
+
+                    merged = self.download(&client)?;
+                    put_chats(&mut merged, games, chats);
+                }
+
+                Err(e) => return Err(dropbox_error(e)),
+            }
+        }
+
+        Err(StorageError::Conflict)
+    }
+}
+
+impl GameStore for DropboxStore {
+    fn load(&self) -> Result<HashMap<String, Game>> {
+        self.download(&self.client())
+    }
+
+    fn save(&self, games: &HashMap<String, Game>) -> Result<()> {
+        let chats = games.keys().cloned().collect::<Vec<_>>();
+        self.upload_merged(games, &chats)
+    }
+
+    fn save_chats(&self, chat_ids: &[String], games: &HashMap<String, Game>) -> Result<()> {
+        self.upload_merged(games, chat_ids)
+    }
+
+    fn save_snapshot(&self, chat_id: &str, snapshot: &SnapshotInfo, game: &Game) -> Result<()> {
+        let path = format!("{}/{}/{}", SNAPSHOTS_DIR, chat_id, snapshot.file_name());
+        let body = schema::game_to_string(chat_id, game)?;
+        upload_file(&self.client(), &path, WriteMode::Add, body.as_bytes())?
+            .map_err(dropbox_error)?;
+        Ok(())
+    }
+
+    fn list_snapshots(&self, chat_id: &str) -> Result<Vec<SnapshotInfo>> {
+        let client = self.client();
+        let arg = ListFolderArg::new(format!("{}/{}", SNAPSHOTS_DIR, chat_id));
+        let mut result = match list_folder(&client, &arg).map_err(dropbox_error)? {
+            Ok(result) => result,
+            Err(ListFolderError::Path(LookupError::NotFound)) => return Ok(vec![]),
+            Err(e) => return Err(dropbox_error(e)),
+        };
+
+        let mut snapshots = vec![];
+        loop {
+            snapshots.extend(result.entries.iter().filter_map(|entry| match entry {
+                Metadata::File(file) => SnapshotInfo::from_file_name(&file.name),
+                _ => None,
+            }));
+
+            if !result.has_more {
+                break;
+            }
+
+            let arg = ListFolderContinueArg::new(result.cursor.clone());
+            result = list_folder_continue(&client, &arg)
+                .map_err(dropbox_error)?
+                .map_err(dropbox_error)?;
+        }
+        snapshots.sort_by_key(|snapshot| snapshot.id);
+
+        Ok(snapshots)
+    }
+
+    fn load_snapshot(&self, chat_id: &str, id: u64) -> Result<Option<Game>> {
+        let snapshot = self
+            .list_snapshots(chat_id)?
+            .into_iter()
+            .find(|snapshot| snapshot.id == id);
+
+        if let Some(snapshot) = snapshot {
+            let path = format!("{}/{}/{}", SNAPSHOTS_DIR, chat_id, snapshot.file_name());
+            if let Some((_, Some(body))) = download_file(&self.client(), &path)? {
+                return schema::game_from_reader(chat_id, body);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn delete_snapshot(&self, chat_id: &str, snapshot: &SnapshotInfo) -> Result<()> {
+        let path = format!("{}/{}/{}", SNAPSHOTS_DIR, chat_id, snapshot.file_name());
+        match delete_v2(&self.client(), &DeleteArg::new(path)).map_err(dropbox_error)? {
+            Ok(_) | Err(DeleteError::PathLookup(LookupError::NotFound)) => Ok(()),
+            Err(e) => Err(dropbox_error(e)),
+        }
+    }
+}
+
+/// Replaces the games of the `chats` with those in `games`, removing the missing ones.
+fn put_chats(remote: &mut HashMap<String, Game>, games: &HashMap<String, Game>, chats: &[String]) {
+    for chat_id in chats {
+        match games.get(chat_id) {
+            Some(game) => {
+                remote.insert(chat_id.clone(), game.clone());
+            }
+            None => {
+                remote.remove(chat_id);
+            }
+        }
+    }
+}
+
+fn dropbox_error<E: std::fmt::Debug>(e: E) -> StorageError {
+    StorageError::Dropbox(format!("{:?}", e))
+}
+
+/// Metadata and contents of a downloaded file.
+type Download = (FileMetadata, Option<Box<dyn Read>>);
+
+/// Downloads the file, `None` if there is no such file.
+fn download_file(client: &HyperClient, path: &str) -> Result<Option<Download>> {
+    let arg = DownloadArg {
+        path: path.to_owned(),
+        rev: None,
+    };
+
+    match download(client, &arg, None, None).map_err(dropbox_error)? {
+        Ok(res) => Ok(Some((res.result, res.body))),
+        Err(DownloadError::Path(LookupError::NotFound)) => Ok(None),
+        Err(e) => Err(dropbox_error(e)),
+    }
+}
+
+fn upload_file(
+    client: &HyperClient,
+    path: &str,
+    mode: WriteMode,
+    body: &[u8],
+) -> Result<std::result::Result<FileMetadata, UploadError>> {
+    let arg = CommitInfo {
+        path: path.to_owned(),
+        mode,
+        autorename: false,
+        client_modified: None,
+        mute: false,
+        property_groups: None,
+        strict_conflict: true,
+    };
+
+    upload(client, &arg, body).map_err(dropbox_error)
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+
+use crate::storage::{schema, GameStore, Result, SnapshotInfo};
+use crate::types::Game;
+
+const DEFAULT_FILE_NAME: &str = "games.yaml";
+
+/// Keeps the games in a YAML file on the local filesystem, and the snapshots in
+/// a directory per chat next to it.
+pub(crate) struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    pub fn from_env() -> Self {
+        FileStore {
+            path: std::env::var("GAMES_FILE")
+                .unwrap_or_else(|_| DEFAULT_FILE_NAME.to_owned())
+                .into(),
+        }
+    }
+}
+
+impl FileStore {
+    fn snapshots_dir(&self, chat_id: &str) -> PathBuf {
+        self.path.with_extension("snapshots").join(chat_id)
+    }
+}
+
+impl GameStore for FileStore {
+    fn load(&self) -> Result<HashMap<String, Game>> {
+        match File::open(&self.path) {
+            Ok(file) => schema::from_reader(file),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Default::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, games: &HashMap<String, Game>) -> Result<()> {
+        // Write to a temporary file first so a crash never leaves a truncated file behind
+        let tmp_path = self.path.with_extension("yaml.tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(schema::to_string(games)?.as_bytes())?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    fn save_snapshot(&self, chat_id: &str, snapshot: &SnapshotInfo, game: &Game) -> Result<()> {
+        let dir = self.snapshots_dir(chat_id);
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(
+            dir.join(snapshot.file_name()),
+            schema::game_to_string(chat_id, game)?,
+        )?;
+        Ok(())
+    }
+
+    fn list_snapshots(&self, chat_id: &str) -> Result<Vec<SnapshotInfo>> {
+        let entries = match std::fs::read_dir(self.snapshots_dir(chat_id)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut snapshots = vec![];
+        for entry in entries {
+            if let Some(snapshot) = entry?
+                .file_name()
+                .to_str()
+                .and_then(SnapshotInfo::from_file_name)
+            {
+                snapshots.push(snapshot);
+            }
+        }
+        snapshots.sort_by_key(|snapshot| snapshot.id);
+
+        Ok(snapshots)
+    }
+
+    fn load_snapshot(&self, chat_id: &str, id: u64) -> Result<Option<Game>> {
+        let snapshot = self
+            .list_snapshots(chat_id)?
+            .into_iter()
+            .find(|snapshot| snapshot.id == id);
+
+        match snapshot {
+            Some(snapshot) => {
+                let file = File::open(self.snapshots_dir(chat_id).join(snapshot.file_name()))?;
+                schema::game_from_reader(chat_id, file)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn delete_snapshot(&self, chat_id: &str, snapshot: &SnapshotInfo) -> Result<()> {
+        match std::fs::remove_file(self.snapshots_dir(chat_id).join(snapshot.file_name())) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::SnapshotReason;
+    use crate::types::GameMessage;
+
+    fn store(name: &str) -> FileStore {
+        let dir =
+            std::env::temp_dir().join(format!("skate-tg-bot-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        FileStore {
+            path: dir.join(DEFAULT_FILE_NAME),
+        }
+    }
+
+    #[test]
+    fn snapshots_move_to_the_new_chat() {
+        let store = store("move");
+        let game = Game {
+            game_message: Some(GameMessage { id: 5, chat_id: -1 }),
+            ..Default::default()
+        };
+        let snapshot = SnapshotInfo {
+            id: 1600000000,
+            reason: SnapshotReason::Reset,
+        };
+        store.save_snapshot("-1", &snapshot, &game).unwrap();
+
+        store.move_snapshots("-1", -1001).unwrap();
+
+        assert!(store.list_snapshots("-1").unwrap().is_empty());
+        assert_eq!(store.list_snapshots("-1001").unwrap(), vec![snapshot]);
+        let moved = store.load_snapshot("-1001", 1600000000).unwrap().unwrap();
+        assert_eq!(
+            moved.game_message,
+            Some(GameMessage {
+                id: 5,
+                chat_id: -1001
+            })
+        );
+    }
+
+    #[test]
+    fn deleting_a_missing_snapshot_succeeds() {
+        let store = store("delete");
+        let snapshot = SnapshotInfo {
+            id: 1,
+            reason: SnapshotReason::Daily,
+        };
+        store.delete_snapshot("-1", &snapshot).unwrap();
+    }
+}
@@ -0,0 +1,501 @@
+//! Versioned format of the YAML documents the games are saved to.
+//!
+//! A document is an envelope with the schema version and the games keyed by chat id.
+//! Older documents are upgraded on load by running every migration from their version
+//! up to [`VERSION`], each one rewriting the untyped YAML of the games.
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use chrono::Utc;
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+
+use crate::storage::{Result, StorageError};
+use crate::types::Game;
+
+/// Migrates the games from the version equal to its index to the next one.
+type Migration = fn(Value) -> Result<Value>;
+
+const MIGRATIONS: &[Migration] = &[from_unversioned];
+
+/// Version of the documents written by this build.
+pub(crate) const VERSION: u64 = MIGRATIONS.len() as u64;
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    version: u64,
+    games: &'a HashMap<String, Game>,
+}
+
+pub(crate) fn to_string(games: &HashMap<String, Game>) -> Result<String> {
+    Ok(serde_yaml::to_string(&Envelope {
+        version: VERSION,
+        games,
+    })?)
+}
+
+pub(crate) fn from_reader<R: Read>(reader: R) -> Result<HashMap<String, Game>> {
+    let document: Value = serde_yaml::from_reader(reader)?;
+    let (version, mut games) = split_envelope(document);
+    if version > VERSION {
+        return Err(StorageError::UnsupportedVersion(version));
+    }
+
+    for migration in MIGRATIONS.iter().skip(version as usize) {
+        games = migration(games)?;
+    }
+
+    Ok(serde_yaml::from_value(games)?)
+}
+
+/// Writes the game of a single chat, as kept in snapshots.
+pub(crate) fn game_to_string(chat_id: &str, game: &Game) -> Result<String> {
+    let mut games = HashMap::new();
+    games.insert(chat_id.to_owned(), game.clone());
+    to_string(&games)
+}
+
+pub(crate) fn game_from_reader<R: Read>(chat_id: &str, reader: R) -> Result<Option<Game>> {
+    Ok(from_reader(reader)?.remove(chat_id))
+}
+
+/// Reads a document holding the game of a single chat, whichever chat it was
+/// written for, as uploaded by `/export`.
+pub(crate) fn single_game_from_reader<R: Read>(reader: R) -> Result<Option<Game>> {
+    let games = from_reader(reader)?;
+    if games.len() != 1 {
+        return Ok(None);
+    }
+    Ok(games.into_iter().next().map(|(_, game)| game))
+}
+
+/// Documents written before the envelope was introduced are the bare map of games
+/// and are treated as version 0.
+fn split_envelope(document: Value) -> (u64, Value) {
+    if let Value::Mapping(ref envelope) = document {
+        let version = envelope
+            .get(&Value::from("version"))
+            .and_then(Value::as_u64);
+        let games = envelope.get(&Value::from("games"));
+        if let (Some(version), Some(games)) = (version, games) {
+            return (version, games.clone());
+        }
+    }
+
+    (0, document)
+}
+
+/// Version 0 to 1: the games get everything they gained along with the envelope. Tricks
+/// get ids equal to the numbers they were shown with, so that the numbers the proofs
+/// refer to stay the same, and the settings are those of the rules the games were played
+/// by. Tricks are worth a point each, like they were counted, and the open challenge is
+/// keyed by the message of its proof, getting all of its voting time from the upgrade.
+fn from_unversioned(games: Value) -> Result<Value> {
+    // Trick limit the positional numbers were derived from
+    const TRICKS_PER_PARTICIPANT: u64 = 3;
+
+    let now = Utc::now().timestamp();
+    for_each_game(games, |game| {
+        let mut next_trick_id = 1;
+        for_each_participant_tricks(game, |participant_index, tricks| {
+            for (trick_index, trick) in tricks.iter_mut().enumerate() {
+                let id = participant_index as u64 * TRICKS_PER_PARTICIPANT + trick_index as u64 + 1;
+                if let Value::Mapping(trick) = trick {
+                    let edited = trick
+                        .remove(&Value::from("edited"))
+                        .and_then(|edited| edited.as_bool())
+                        .unwrap_or(false);
+                    trick.insert(Value::from("id"), Value::from(id));
+                    trick.insert(Value::from("renames"), Value::from(edited as u64));
+                    trick.insert(Value::from("points"), Value::from(1));
+                    trick.insert(Value::from("points_set"), Value::from(false));
+                }
+                next_trick_id = next_trick_id.max(id + 1);
+            }
+        });
+        game.insert(Value::from("next_trick_id"), Value::from(next_trick_id));
+
+        // The files of the proofs weren't kept
+        for_each_proof(game, |proof| {
+            proof.insert(Value::from("proven_at"), Value::from(0));
+            proof.insert(Value::from("replaced"), Value::Sequence(vec![]));
+            proof.insert(Value::from("file"), Value::Null);
+            proof.insert(Value::from("replaced_files"), Value::Sequence(vec![]));
+        });
+
+        let mut settings = Mapping::new();
+        settings.insert(Value::from("max_tricks"), Value::from(3));
+        settings.insert(Value::from("max_renames"), Value::from(1));
+        settings.insert(Value::from("videos_only"), Value::from(true));
+        settings.insert(Value::from("quorum_percent"), Value::from(50));
+        settings.insert(Value::from("own_tricks"), Value::from("Allowed"));
+        settings.insert(Value::from("vote_hours"), Value::from(24));
+        settings.insert(Value::from("min_turnout_percent"), Value::from(20));
+        settings.insert(Value::from("voting"), Value::from("Majority"));
+        settings.insert(Value::from("quorum_votes"), Value::from(3));
+        settings.insert(Value::from("owner_votes"), Value::from(true));
+        game.insert(Value::from("settings"), Value::Mapping(settings));
+
+        game.insert(Value::from("finished_at"), Value::Null);
+        game.insert(Value::from("history"), Value::Sequence(vec![]));
+        game.insert(Value::from("deadline"), Value::Null);
+        game.insert(Value::from("last_reminder"), Value::Null);
+        game.insert(Value::from("duel"), Value::Null);
+
+        let mut challenges = Mapping::new();
+        if let Some(Value::Mapping(mut challenge)) = game.remove(&Value::from("proof_challenge")) {
+            let msg = match challenge.get(&Value::from("proof")) {
+                Some(Value::Mapping(proof)) => proof.get(&Value::from("msg")).cloned(),
+                _ => None,
+            };
+            if let Some(msg) = msg {
+                votes_from_counts(&mut challenge);
+                challenge.insert(Value::from("opened_at"), Value::from(now));
+                challenges.insert(msg, Value::Mapping(challenge));
+            }
+        }
+        game.insert(Value::from("proof_challenges"), Value::Mapping(challenges));
+
+        Ok(())
+    })
+}
+
+/// Replaces the counts and the voters of a poll with the vote of each voter. Which way
+/// each one voted was never stored, so the first voters get the yes votes.
+fn votes_from_counts(poll: &mut Mapping) {
+    let num_yes = poll
+        .remove(&Value::from("num_yes"))
+        .and_then(|num| num.as_u64())
+        .unwrap_or(0) as usize;
+    let num_no = poll
+        .remove(&Value::from("num_no"))
+        .and_then(|num| num.as_u64())
+        .unwrap_or(0) as usize;
+    let voters = match poll.remove(&Value::from("voters")) {
+        Some(Value::Sequence(voters)) => voters,
+        _ => Vec::new(),
+    };
+
+    let mut votes = Mapping::new();
+    for (position, voter) in voters.into_iter().take(num_yes + num_no).enumerate() {
+        votes.insert(voter, Value::from(position < num_yes));
+    }
+    poll.insert(Value::from("votes"), Value::Mapping(votes));
+}
+
+/// Rewrites the untyped YAML of every game, including the finished games in the history
+/// of each chat.
+fn for_each_game<F>(mut games: Value, mut f: F) -> Result<Value>
+where
+    F: FnMut(&mut Mapping) -> Result<()>,
+{
+    if let Value::Mapping(ref mut games) = games {
+        for key in keys(games) {
+            if let Some(Value::Mapping(game)) = games.get_mut(&key) {
+                rewrite_game(game, &mut f)?;
+            }
+        }
+    }
+
+    Ok(games)
+}
+
+fn rewrite_game<F>(game: &mut Mapping, f: &mut F) -> Result<()>
+where
+    F: FnMut(&mut Mapping) -> Result<()>,
+{
+    // Rewrite the history first, a migration adding it leaves it empty anyway
+    if let Some(Value::Sequence(history)) = game.get_mut(&Value::from("history")) {
+        for finished in history.iter_mut() {
+            if let Value::Mapping(finished) = finished {
+                rewrite_game(finished, f)?;
+            }
+        }
+    }
+
+    f(game)
+}
+
+/// Runs `f` on the tricks of every participant of the game, along with the
+/// participant's position. The copies of the participants kept by open challenges
+/// are visited too, with the positions of the challenged participants.
+fn for_each_participant_tricks<F>(game: &mut Mapping, mut f: F)
+where
+    F: FnMut(usize, &mut Vec<Value>),
+{
+    let mut indexes = HashMap::new();
+    if let Some(Value::Mapping(participants)) = game.get_mut(&Value::from("participants")) {
+        for (index, key) in keys(participants).iter().enumerate() {
+            if let Some(id) = user_id(key) {
+                indexes.insert(id, index);
+            }
+            if let Some(tricks) = tricks_mut(participants.get_mut(key)) {
+                f(index, tricks);
+            }
+        }
+    }
+
+    for_each_challenge(game, |challenge| {
+        let index = challenge
+            .get(&Value::from("user"))
+            .and_then(user_id)
+            .and_then(|id| indexes.get(&id).copied());
+        if let (Some(index), Some(tricks)) = (
+            index,
+            tricks_mut(challenge.get_mut(&Value::from("participant"))),
+        ) {
+            f(index, tricks);
+        }
+    });
+}
+
+/// Runs `f` on every proof of the game, including the challenged ones and the copies
+/// kept by open challenges.
+fn for_each_proof<F>(game: &mut Mapping, mut f: F)
+where
+    F: FnMut(&mut Mapping),
+{
+    fn visit_proofs<F: FnMut(&mut Mapping)>(participant: Option<&mut Value>, f: &mut F) {
+        if let Some(Value::Mapping(participant)) = participant {
+            if let Some(Value::Sequence(proofs)) = participant.get_mut(&Value::from("proofs")) {
+                for proof in proofs.iter_mut() {
+                    if let Value::Mapping(proof) = proof {
+                        f(proof);
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(Value::Mapping(participants)) = game.get_mut(&Value::from("participants")) {
+        for key in keys(participants) {
+            visit_proofs(participants.get_mut(&key), &mut f);
+        }
+    }
+
+    for_each_challenge(game, |challenge| {
+        visit_proofs(challenge.get_mut(&Value::from("participant")), &mut f);
+        if let Some(Value::Mapping(proof)) = challenge.get_mut(&Value::from("proof")) {
+            f(proof);
+        }
+    });
+}
+
+/// Runs `f` on every open challenge of the game, kept in `proof_challenges` or, before
+/// version 1, as the single `proof_challenge`.
+fn for_each_challenge<F>(game: &mut Mapping, mut f: F)
+where
+    F: FnMut(&mut Mapping),
+{
+    if let Some(Value::Mapping(challenge)) = game.get_mut(&Value::from("proof_challenge")) {
+        f(challenge);
+    }
+
+    if let Some(Value::Mapping(challenges)) = game.get_mut(&Value::from("proof_challenges")) {
+        for key in keys(challenges) {
+            if let Some(Value::Mapping(challenge)) = challenges.get_mut(&key) {
+                f(challenge);
+            }
+        }
+    }
+}
+
+fn tricks_mut(participant: Option<&mut Value>) -> Option<&mut Vec<Value>> {
+    match participant {
+        Some(Value::Mapping(participant)) => match participant.get_mut(&Value::from("tricks")) {
+            Some(Value::Sequence(tricks)) => Some(tricks),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn user_id(user: &Value) -> Option<i64> {
+    match user {
+        Value::Mapping(user) => user.get(&Value::from("id")).and_then(Value::as_i64),
+        _ => None,
+    }
+}
+
+fn keys(mapping: &Mapping) -> Vec<Value> {
+    mapping.iter().map(|(key, _)| key.clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{GameMessage, GameUser, OwnTricks, VideoFile};
+    use crate::voting::VotingPolicy;
+
+    /// Games as saved before the envelope, and as saved by this build.
+    const UNVERSIONED: &str = include_str!("fixtures/v0.yaml");
+    const CURRENT: &str = include_str!("fixtures/v1.yaml");
+
+    const CHAT_ID: &str = "-100123";
+
+    fn load(document: &str) -> HashMap<String, Game> {
+        from_reader(document.as_bytes()).unwrap()
+    }
+
+    fn msg(id: i64) -> GameMessage {
+        GameMessage {
+            id,
+            chat_id: -100123,
+        }
+    }
+
+    fn user(id: i64) -> GameUser {
+        GameUser {
+            id,
+            first_name: Default::default(),
+            username: None,
+        }
+    }
+
+    #[test]
+    fn loads_both_versions() {
+        for document in &[UNVERSIONED, CURRENT] {
+            let games = load(document);
+            let game = &games[CHAT_ID];
+            assert_eq!(game.participants.len(), 2);
+            assert_eq!(
+                game.participants[&user(1)].proofs[0].tricks_proven,
+                vec![1, 2]
+            );
+            assert_eq!(
+                game.proof_challenges.keys().collect::<Vec<_>>(),
+                vec![&msg(10)]
+            );
+            assert!(!games["42"].started());
+        }
+    }
+
+    #[test]
+    fn unversioned_games_get_the_rules_they_were_played_by() {
+        let games = load(UNVERSIONED);
+        let game = &games[CHAT_ID];
+        assert_eq!(game.settings.max_tricks, 3);
+        assert_eq!(game.settings.own_tricks, OwnTricks::Allowed);
+        assert_eq!(game.settings.voting, VotingPolicy::Majority);
+        assert!(game.history.is_empty() && game.finished_at.is_none());
+        assert!(game.deadline.is_none() && game.duel.is_none());
+
+        let bob = &game.participants[&user(2)];
+        assert_eq!(bob.tricks[0].points, 1);
+        assert!(bob.proofs[0].replaced.is_empty() && bob.proofs[0].file.is_none());
+    }
+
+    #[test]
+    fn tricks_get_ids_of_their_positions() {
+        let games = load(UNVERSIONED);
+        let game = &games[CHAT_ID];
+        let ids = |id| {
+            game.participants[&user(id)]
+                .tricks
+                .iter()
+                .map(|trick| trick.id)
+                .collect::<Vec<_>>()
+        };
+
+        // Three numbers per participant, whether or not all the tricks were added
+        assert_eq!(ids(1), vec![1, 2]);
+        assert_eq!(ids(2), vec![4]);
+        assert_eq!(game.next_trick_id, 5);
+        assert_eq!(game.trick_by_id(2).map(|trick| trick.edited()), Some(true));
+        assert_eq!(
+            game.trick_by_id(4).map(|trick| trick.name),
+            Some("ollie".to_owned())
+        );
+
+        // The proof still refers to the trick it was posted for
+        let proof = &game.participants[&user(2)].proofs[0];
+        assert_eq!(game.trick_owner(proof.tricks_proven[0]), Some(user(1)));
+
+        // So does the copy of the participant kept by the challenge
+        let challenge = &game.proof_challenges[&msg(10)];
+        assert_eq!(challenge.participant.tricks[1].id, 2);
+    }
+
+    #[test]
+    fn single_challenge_is_keyed_by_its_proof() {
+        let now = Utc::now().timestamp() as u64;
+        let games = load(UNVERSIONED);
+        let challenge = &games[CHAT_ID].proof_challenges[&msg(10)];
+        assert_eq!(challenge.user, user(1));
+        assert_eq!(challenge.proof.msg, msg(10));
+        assert_eq!(challenge.poll_msg, msg(11));
+
+        // Gets all of the voting time from the upgrade
+        assert!(challenge.opened_at + 60 >= now && challenge.opened_at <= now);
+        assert!(games["42"].proof_challenges.is_empty());
+    }
+
+    #[test]
+    fn votes_are_taken_from_the_counts() {
+        let games = load(UNVERSIONED);
+        let votes = &games[CHAT_ID].proof_challenges[&msg(10)].votes;
+        assert_eq!(votes.iter().collect::<Vec<_>>(), vec![(&user(2), &true)]);
+    }
+
+    #[test]
+    fn voters_beyond_the_counts_are_dropped() {
+        let games = load(&UNVERSIONED.replace("num_yes: 1", "num_yes: 0"));
+        let votes = &games[CHAT_ID].proof_challenges[&msg(10)].votes;
+        assert!(votes.is_empty());
+    }
+
+    #[test]
+    fn points_are_scored_anew_unless_known_to_be_set() {
+        for (document, expected) in &[(UNVERSIONED, vec![]), (CURRENT, vec![5])] {
+            let games = load(document);
+            let points_set = games[CHAT_ID]
+                .participants
+                .values()
+                .flat_map(|participant| participant.tricks.iter())
+                .filter(|trick| trick.points_set)
+                .map(|trick| trick.id)
+                .collect::<Vec<_>>();
+            assert_eq!(&points_set, expected);
+        }
+    }
+
+    #[test]
+    fn current_version_reads_back() {
+        let games = load(CURRENT);
+        let game = &games[CHAT_ID];
+        assert_eq!(game.history[0].finished_at, Some(1600000000));
+        assert_eq!(game.settings.voting, VotingPolicy::Supermajority);
+        assert_eq!(game.participants[&user(2)].proofs[0].replaced, vec![msg(8)]);
+        assert_eq!(
+            game.participants[&user(2)].proofs[0].replaced_files,
+            vec![VideoFile {
+                unique_id: "AgADold".to_owned(),
+                duration: Some(6),
+                size: None,
+            }]
+        );
+        let dispute = game.duel.as_ref().and_then(|duel| duel.dispute.as_ref());
+        assert_eq!(dispute.map(|dispute| dispute.opened_at), Some(1700000600));
+
+        let text = to_string(&games).unwrap();
+        assert!(text.contains(&format!("version: {}", VERSION)));
+
+        let loaded = from_reader(text.as_bytes()).unwrap();
+        for (chat_id, game) in &games {
+            assert_eq!(
+                serde_yaml::to_string(&loaded[chat_id]).unwrap(),
+                serde_yaml::to_string(game).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let document = format!("version: {}\ngames: {{}}\n", VERSION + 1);
+        match from_reader(document.as_bytes()) {
+            Err(StorageError::UnsupportedVersion(version)) => assert_eq!(version, VERSION + 1),
+            other => panic!("unexpected result: {:?}", other.map(|games| games.len())),
+        }
+    }
+}
@@ -0,0 +1,1210 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection, Transaction, NO_PARAMS};
+
+use crate::storage::{schema, GameStore, Result, SnapshotInfo, SnapshotReason};
+use crate::types::*;
+use crate::voting::VotingPolicy;
+
+const DEFAULT_FILE_NAME: &str = "games.sqlite";
+
+/// Schema migrations, applied in order. `PRAGMA user_version` holds the number of
+/// migrations already applied to the database.
+const MIGRATIONS: &[&str] = &[
+    // Finished games go in the rows of the current one under their round, the current
+    // game is round 0
+    "
+    CREATE TABLE chats (
+        chat_id TEXT NOT NULL,
+        round INTEGER NOT NULL,
+        is_started INTEGER NOT NULL,
+        game_message_id INTEGER,
+        game_message_chat_id INTEGER,
+        next_trick_id INTEGER NOT NULL,
+        max_tricks INTEGER NOT NULL,
+        max_renames INTEGER NOT NULL,
+        videos_only INTEGER NOT NULL,
+        quorum_percent INTEGER NOT NULL,
+        finished_at INTEGER,
+        deadline INTEGER,
+        last_reminder INTEGER,
+        own_tricks TEXT NOT NULL,
+        vote_hours INTEGER NOT NULL,
+        min_turnout_percent INTEGER NOT NULL,
+        voting TEXT NOT NULL,
+        quorum_votes INTEGER NOT NULL,
+        owner_votes INTEGER NOT NULL,
+        PRIMARY KEY (chat_id, round)
+    );
+
+    CREATE TABLE participants (
+        chat_id TEXT NOT NULL,
+        round INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        first_name TEXT NOT NULL,
+        username TEXT,
+        PRIMARY KEY (chat_id, round, user_id)
+    );
+
+    CREATE TABLE tricks (
+        chat_id TEXT NOT NULL,
+        round INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        trick_id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        renames INTEGER NOT NULL,
+        points INTEGER NOT NULL,
+        points_set INTEGER NOT NULL,
+        PRIMARY KEY (chat_id, round, user_id, position)
+    );
+
+    CREATE TABLE proofs (
+        chat_id TEXT NOT NULL,
+        round INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        msg_id INTEGER NOT NULL,
+        msg_chat_id INTEGER NOT NULL,
+        tricks_proven TEXT NOT NULL,
+        proven_at INTEGER NOT NULL,
+        file_unique_id TEXT,
+        file_duration INTEGER,
+        file_size INTEGER,
+        PRIMARY KEY (chat_id, round, user_id, position)
+    );
+
+    CREATE TABLE replaced_proofs (
+        chat_id TEXT NOT NULL,
+        round INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        proof_position INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        msg_id INTEGER NOT NULL,
+        msg_chat_id INTEGER NOT NULL,
+        PRIMARY KEY (chat_id, round, user_id, proof_position, position)
+    );
+
+    CREATE TABLE replaced_files (
+        chat_id TEXT NOT NULL,
+        round INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        proof_position INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        unique_id TEXT NOT NULL,
+        duration INTEGER,
+        size INTEGER,
+        PRIMARY KEY (chat_id, round, user_id, proof_position, position)
+    );
+
+    CREATE TABLE challenges (
+        chat_id TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        proof_msg_id INTEGER NOT NULL,
+        proof_msg_chat_id INTEGER NOT NULL,
+        tricks_proven TEXT NOT NULL,
+        poll_msg_id INTEGER NOT NULL,
+        poll_msg_chat_id INTEGER NOT NULL,
+        proven_at INTEGER NOT NULL,
+        opened_at INTEGER NOT NULL,
+        PRIMARY KEY (chat_id, position)
+    );
+
+    CREATE TABLE challenge_voters (
+        chat_id TEXT NOT NULL,
+        proof_msg_id INTEGER NOT NULL,
+        proof_msg_chat_id INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        first_name TEXT NOT NULL,
+        username TEXT,
+        vote INTEGER NOT NULL,
+        PRIMARY KEY (chat_id, proof_msg_id, proof_msg_chat_id, position)
+    );
+
+    CREATE TABLE duels (
+        chat_id TEXT PRIMARY KEY,
+        setter INTEGER NOT NULL,
+        set_trick_name TEXT,
+        set_msg_id INTEGER,
+        set_msg_chat_id INTEGER,
+        landed_trick_name TEXT,
+        landed_msg_id INTEGER,
+        landed_msg_chat_id INTEGER,
+        poll_msg_id INTEGER,
+        poll_msg_chat_id INTEGER,
+        opened_at INTEGER NOT NULL
+    );
+
+    CREATE TABLE duel_players (
+        chat_id TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        first_name TEXT NOT NULL,
+        username TEXT,
+        letters INTEGER NOT NULL,
+        PRIMARY KEY (chat_id, position)
+    );
+
+    CREATE TABLE duel_voters (
+        chat_id TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        first_name TEXT NOT NULL,
+        username TEXT,
+        vote INTEGER NOT NULL,
+        PRIMARY KEY (chat_id, position)
+    );
+
+    CREATE TABLE snapshots (
+        chat_id TEXT NOT NULL,
+        id INTEGER NOT NULL,
+        reason TEXT NOT NULL,
+        game TEXT NOT NULL,
+        PRIMARY KEY (chat_id, id, reason)
+    );
+",
+];
+
+/// Tables holding the rows of a game, either the current one or a finished one, told
+/// apart by `round`. The rows of the current game are cleared and refilled when a chat
+/// is saved, those of the finished ones are written once.
+const ROUND_TABLES: &[&str] = &[
+    "chats",
+    "participants",
+    "tricks",
+    "proofs",
+    "replaced_proofs",
+    "replaced_files",
+];
+
+/// Tables holding the rows of the current game only, cleared and refilled when a chat
+/// is saved. Snapshots are kept apart from those.
+const CHAT_TABLES: &[&str] = &[
+    "challenges",
+    "challenge_voters",
+    "duels",
+    "duel_players",
+    "duel_voters",
+];
+
+/// Round of the current game of a chat, the finished ones are numbered from 1.
+const CURRENT_ROUND: i64 = 0;
+
+/// Keeps the games in an embedded SQLite database, one set of rows per chat.
+pub(crate) struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Opens the database and brings its schema up to date.
+    pub fn from_env() -> Result<Self> {
+        let path = std::env::var("SQLITE_PATH").unwrap_or_else(|_| DEFAULT_FILE_NAME.to_owned());
+        let mut conn = Connection::open(path)?;
+        migrate(&mut conn)?;
+
+        Ok(SqliteStore {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+impl GameStore for SqliteStore {
+    fn load(&self) -> Result<HashMap<String, Game>> {
+        let conn = self.conn.lock().unwrap();
+        load_chats(&conn)
+    }
+
+    fn save(&self, games: &HashMap<String, Game>) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for (chat_id, game) in games {
+            write_chat(&tx, chat_id, game)?;
+        }
+        Ok(tx.commit()?)
+    }
+
+    fn save_chats(&self, chat_ids: &[String], games: &HashMap<String, Game>) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for chat_id in chat_ids {
+            match games.get(chat_id) {
+                Some(game) => write_chat(&tx, chat_id, game)?,
+                None => delete_chat(&tx, chat_id)?,
+            }
+        }
+        Ok(tx.commit()?)
+    }
+
+    fn save_snapshot(&self, chat_id: &str, snapshot: &SnapshotInfo, game: &Game) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO snapshots (chat_id, id, reason, game) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                chat_id,
+                snapshot.id as i64,
+                snapshot.reason.as_str(),
+                schema::game_to_string(chat_id, game)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn list_snapshots(&self, chat_id: &str) -> Result<Vec<SnapshotInfo>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare("SELECT id, reason FROM snapshots WHERE chat_id = ?1 ORDER BY id")?;
+        let rows = stmt.query_map(params![chat_id], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut snapshots = vec![];
+        for row in rows {
+            let (id, reason) = row?;
+            if let Some(reason) = SnapshotReason::parse(&reason) {
+                snapshots.push(SnapshotInfo {
+                    id: id as u64,
+                    reason,
+                });
+            }
+        }
+
+        Ok(snapshots)
+    }
+
+    fn load_snapshot(&self, chat_id: &str, id: u64) -> Result<Option<Game>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT game FROM snapshots WHERE chat_id = ?1 AND id = ?2")?;
+        let mut rows =
+            stmt.query_map(params![chat_id, id as i64], |row| row.get::<_, String>(0))?;
+
+        match rows.next() {
+            Some(game) => schema::game_from_reader(chat_id, game?.as_bytes()),
+            None => Ok(None),
+        }
+    }
+
+    fn delete_snapshot(&self, chat_id: &str, snapshot: &SnapshotInfo) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM snapshots WHERE chat_id = ?1 AND id = ?2 AND reason = ?3",
+            params![chat_id, snapshot.id as i64, snapshot.reason.as_str()],
+        )?;
+        Ok(())
+    }
+}
+
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize =
+        conn.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get::<_, i64>(0))? as usize;
+
+    let tx = conn.transaction()?;
+    for migration in MIGRATIONS.iter().skip(version) {
+        tx.execute_batch(migration)?;
+    }
+    tx.execute_batch(&format!("PRAGMA user_version = {}", MIGRATIONS.len()))?;
+    tx.commit()
+}
+
+fn delete_chat(tx: &Transaction, chat_id: &str) -> rusqlite::Result<()> {
+    for table in ROUND_TABLES.iter().chain(CHAT_TABLES) {
+        tx.execute(
+            &format!("DELETE FROM {} WHERE chat_id = ?1", table),
+            params![chat_id],
+        )?;
+    }
+    Ok(())
+}
+
+/// Deletes the rows of the rounds from `first` to `last`, both included.
+fn delete_rounds(tx: &Transaction, chat_id: &str, first: i64, last: i64) -> rusqlite::Result<()> {
+    for table in ROUND_TABLES {
+        tx.execute(
+            &format!(
+                "DELETE FROM {} WHERE chat_id = ?1 AND round BETWEEN ?2 AND ?3",
+                table
+            ),
+            params![chat_id, first, last],
+        )?;
+    }
+    Ok(())
+}
+
+fn write_chat(tx: &Transaction, chat_id: &str, game: &Game) -> Result<()> {
+    delete_rounds(tx, chat_id, CURRENT_ROUND, CURRENT_ROUND)?;
+    for table in CHAT_TABLES {
+        tx.execute(
+            &format!("DELETE FROM {} WHERE chat_id = ?1", table),
+            params![chat_id],
+        )?;
+    }
+
+    write_round(tx, chat_id, CURRENT_ROUND, game)?;
+
+    for (position, challenge) in game.proof_challenges.values().enumerate() {
+        tx.execute(
+            "INSERT INTO challenges (chat_id, position, user_id, proof_msg_id, proof_msg_chat_id, \
+             tricks_proven, poll_msg_id, poll_msg_chat_id, proven_at, opened_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                chat_id,
+                position as i64,
+                challenge.user.id,
+                challenge.proof.msg.id,
+                challenge.proof.msg.chat_id,
+                join_numbers(&challenge.proof.tricks_proven),
+                challenge.poll_msg.id,
+                challenge.poll_msg.chat_id,
+                challenge.proof.proven_at as i64,
+                challenge.opened_at as i64,
+            ],
+        )?;
+
+        for (position, (voter, vote)) in challenge.votes.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO challenge_voters (chat_id, proof_msg_id, proof_msg_chat_id, position, \
+                 user_id, first_name, username, vote) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    chat_id,
+                    challenge.proof.msg.id,
+                    challenge.proof.msg.chat_id,
+                    position as i64,
+                    voter.id,
+                    voter.first_name,
+                    voter.username,
+                    vote
+                ],
+            )?;
+        }
+    }
+
+    if let Some(duel) = &game.duel {
+        write_duel(tx, chat_id, duel)?;
+    }
+
+    write_history(tx, chat_id, &game.history)
+}
+
+/// Writes the finished games that aren't stored yet. A finished game doesn't change, so
+/// the stored rounds are kept unless the history was replaced, by a restore or an import,
+/// and doesn't start with them anymore.
+fn write_history(tx: &Transaction, chat_id: &str, history: &[Game]) -> Result<()> {
+    let mut stmt = tx.prepare(
+        "SELECT finished_at FROM chats WHERE chat_id = ?1 AND round > ?2 ORDER BY round",
+    )?;
+    let stored = stmt
+        .query_map(params![chat_id, CURRENT_ROUND], |row| {
+            row.get::<_, Option<i64>>(0)
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let is_kept = stored.len() <= history.len()
+        && stored.iter().zip(history).all(|(finished_at, finished)| {
+            *finished_at == finished.finished_at.map(|at| at as i64)
+        });
+    let num_kept = if is_kept {
+        stored.len()
+    } else {
+        delete_rounds(tx, chat_id, CURRENT_ROUND + 1, i64::MAX)?;
+        0
+    };
+
+    for (position, finished) in history.iter().enumerate().skip(num_kept) {
+        write_round(tx, chat_id, CURRENT_ROUND + 1 + position as i64, finished)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the game along with its participants, without the challenges and the duel
+/// that only the current game has.
+fn write_round(tx: &Transaction, chat_id: &str, round: i64, game: &Game) -> Result<()> {
+    tx.execute(
+        "INSERT INTO chats (chat_id, round, is_started, game_message_id, game_message_chat_id, \
+         next_trick_id, max_tricks, max_renames, videos_only, quorum_percent, finished_at, \
+         deadline, last_reminder, own_tricks, vote_hours, min_turnout_percent, voting, \
+         quorum_votes, owner_votes) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, \
+         ?19)",
+        params![
+            chat_id,
+            round,
+            game.is_started,
+            game.game_message.as_ref().map(|msg| msg.id),
+            game.game_message.as_ref().map(|msg| msg.chat_id),
+            game.next_trick_id as i64,
+            game.settings.max_tricks as i64,
+            game.settings.max_renames as i64,
+            game.settings.videos_only,
+            game.settings.quorum_percent as i64,
+            game.finished_at.map(|finished_at| finished_at as i64),
+            game.deadline.map(|deadline| deadline as i64),
+            game.last_reminder.map(|last_reminder| last_reminder as i64),
+            game.settings.own_tricks.as_str(),
+            game.settings.vote_hours as i64,
+            game.settings.min_turnout_percent as i64,
+            game.settings.voting.as_str(),
+            game.settings.quorum_votes as i64,
+            game.settings.owner_votes,
+        ],
+    )?;
+
+    for (position, (user, participant)) in game.participants.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO participants (chat_id, round, user_id, position, first_name, username) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                chat_id,
+                round,
+                user.id,
+                position as i64,
+                user.first_name,
+                user.username
+            ],
+        )?;
+
+        for (position, trick) in participant.tricks.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO tricks (chat_id, round, user_id, position, trick_id, name, renames, \
+                 points, points_set) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    chat_id,
+                    round,
+                    user.id,
+                    position as i64,
+                    trick.id as i64,
+                    trick.name,
+                    trick.renames as i64,
+                    trick.points as i64,
+                    trick.points_set,
+                ],
+            )?;
+        }
+
+        for (position, proof) in participant.proofs.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO proofs (chat_id, round, user_id, position, msg_id, msg_chat_id, \
+                 tricks_proven, proven_at, file_unique_id, file_duration, file_size) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                params![
+                    chat_id,
+                    round,
+                    user.id,
+                    position as i64,
+                    proof.msg.id,
+                    proof.msg.chat_id,
+                    join_numbers(&proof.tricks_proven),
+                    proof.proven_at as i64,
+                    proof.file.as_ref().map(|file| &file.unique_id),
+                    proof.file.as_ref().and_then(|file| file.duration),
+                    proof.file.as_ref().and_then(|file| file.size),
+                ],
+            )?;
+
+            for (replaced_position, msg) in proof.replaced.iter().enumerate() {
+                tx.execute(
+                    "INSERT INTO replaced_proofs (chat_id, round, user_id, proof_position, \
+                     position, msg_id, msg_chat_id) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        chat_id,
+                        round,
+                        user.id,
+                        position as i64,
+                        replaced_position as i64,
+                        msg.id,
+                        msg.chat_id,
+                    ],
+                )?;
+            }
+
+            for (replaced_position, file) in proof.replaced_files.iter().enumerate() {
+                tx.execute(
+                    "INSERT INTO replaced_files (chat_id, round, user_id, proof_position, \
+                     position, unique_id, duration, size) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    params![
+                        chat_id,
+                        round,
+                        user.id,
+                        position as i64,
+                        replaced_position as i64,
+                        file.unique_id,
+                        file.duration,
+                        file.size,
+                    ],
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_duel(tx: &Transaction, chat_id: &str, duel: &Duel) -> Result<()> {
+    let dispute = duel.dispute.as_ref();
+    tx.execute(
+        "INSERT INTO duels (chat_id, setter, set_trick_name, set_msg_id, set_msg_chat_id, \
+         landed_trick_name, landed_msg_id, landed_msg_chat_id, poll_msg_id, poll_msg_chat_id, \
+         opened_at) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            chat_id,
+            duel.setter as i64,
+            duel.set_trick.as_ref().map(|trick| &trick.name),
+            duel.set_trick.as_ref().map(|trick| trick.msg.id),
+            duel.set_trick.as_ref().map(|trick| trick.msg.chat_id),
+            duel.landed_trick.as_ref().map(|trick| &trick.name),
+            duel.landed_trick.as_ref().map(|trick| trick.msg.id),
+            duel.landed_trick.as_ref().map(|trick| trick.msg.chat_id),
+            dispute.map(|dispute| dispute.poll_msg.id),
+            dispute.map(|dispute| dispute.poll_msg.chat_id),
+            dispute.map_or(0, |dispute| dispute.opened_at as i64),
+        ],
+    )?;
+
+    for (position, player) in duel.players.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO duel_players (chat_id, position, user_id, first_name, username, letters) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                chat_id,
+                position as i64,
+                player.user.id,
+                player.user.first_name,
+                player.user.username,
+                player.letters as i64,
+            ],
+        )?;
+    }
+
+    for (position, (voter, vote)) in dispute
+        .iter()
+        .flat_map(|dispute| dispute.votes.iter())
+        .enumerate()
+    {
+        tx.execute(
+            "INSERT INTO duel_voters (chat_id, position, user_id, first_name, username, vote) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                chat_id,
+                position as i64,
+                voter.id,
+                voter.first_name,
+                voter.username,
+                vote
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+fn load_chats(conn: &Connection) -> Result<HashMap<String, Game>> {
+    let mut games = HashMap::new();
+
+    let mut stmt = conn.prepare(
+        "SELECT chat_id, is_started, game_message_id, game_message_chat_id, next_trick_id, \
+         max_tricks, max_renames, videos_only, quorum_percent, finished_at, deadline, last_reminder, \
+         own_tricks, vote_hours, min_turnout_percent, voting, quorum_votes, owner_votes, round \
+         FROM chats ORDER BY chat_id, round",
+    )?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        let game_message = match (row.get(2)?, row.get(3)?) {
+            (Some(id), Some(chat_id)) => Some(GameMessage { id, chat_id }),
+            _ => None,
+        };
+
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, i64>(18)?,
+            Game {
+                is_started: row.get(1)?,
+                game_message,
+                next_trick_id: row.get::<_, i64>(4)? as usize,
+                settings: GameSettings {
+                    max_tricks: row.get::<_, i64>(5)? as usize,
+                    max_renames: row.get::<_, i64>(6)? as usize,
+                    videos_only: row.get(7)?,
+                    quorum_percent: row.get::<_, i64>(8)? as usize,
+                    own_tricks: OwnTricks::parse(&row.get::<_, String>(12)?)
+                        .unwrap_or(OwnTricks::Allowed),
+                    vote_hours: row.get::<_, i64>(13)? as usize,
+                    min_turnout_percent: row.get::<_, i64>(14)? as usize,
+                    voting: VotingPolicy::parse(&row.get::<_, String>(15)?)
+                        .unwrap_or(VotingPolicy::Majority),
+                    quorum_votes: row.get::<_, i64>(16)? as usize,
+                    owner_votes: row.get(17)?,
+                },
+                finished_at: row.get::<_, Option<i64>>(9)?.map(|at| at as u64),
+                deadline: row.get::<_, Option<i64>>(10)?.map(|at| at as u64),
+                last_reminder: row.get::<_, Option<i64>>(11)?.map(|secs| secs as u64),
+                ..Default::default()
+            },
+        ))
+    })?;
+    for row in rows {
+        // The current game of a chat comes before the finished ones, in the order they
+        // were played
+        let (chat_id, round, game) = row?;
+        if round == CURRENT_ROUND {
+            games.insert(chat_id, game);
+        } else if let Some(current) = games.get_mut(&chat_id) {
+            current.history.push(game);
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT chat_id, round, user_id, first_name, username FROM participants \
+         ORDER BY chat_id, round, position",
+    )?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, i64>(1)?,
+            GameUser {
+                id: row.get(2)?,
+                first_name: row.get(3)?,
+                username: row.get(4)?,
+            },
+        ))
+    })?;
+    for row in rows {
+        let (chat_id, round, user) = row?;
+        if let Some(game) = find_game(&mut games, &chat_id, round) {
+            game.participants.insert(
+                user,
+                Participant {
+                    tricks: vec![],
+                    proofs: vec![],
+                },
+            );
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT chat_id, round, user_id, trick_id, name, renames, points, points_set \
+         FROM tricks ORDER BY chat_id, round, user_id, position",
+    )?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, i64>(1)?,
+            row.get::<_, i64>(2)?,
+            Trick {
+                id: row.get::<_, i64>(3)? as usize,
+                name: row.get(4)?,
+                renames: row.get::<_, i64>(5)? as usize,
+                points: row.get::<_, i64>(6)? as usize,
+                points_set: row.get(7)?,
+            },
+        ))
+    })?;
+    for row in rows {
+        let (chat_id, round, user_id, trick) = row?;
+        if let Some(participant) = find_participant(&mut games, &chat_id, round, user_id) {
+            participant.tricks.push(trick);
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT chat_id, round, user_id, msg_id, msg_chat_id, tricks_proven, proven_at, \
+         file_unique_id, file_duration, file_size FROM proofs \
+         ORDER BY chat_id, round, user_id, position",
+    )?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, i64>(1)?,
+            row.get::<_, i64>(2)?,
+            Proof {
+                msg: GameMessage {
+                    id: row.get(3)?,
+                    chat_id: row.get(4)?,
+                },
+                tricks_proven: split_numbers(&row.get::<_, String>(5)?),
+                proven_at: row.get::<_, i64>(6)? as u64,
+                replaced: vec![],
+                file: match row.get::<_, Option<String>>(7)? {
+                    Some(unique_id) => Some(VideoFile {
+                        unique_id,
+                        duration: row.get(8)?,
+                        size: row.get(9)?,
+                    }),
+                    None => None,
+                },
+                replaced_files: vec![],
+            },
+        ))
+    })?;
+    for row in rows {
+        let (chat_id, round, user_id, proof) = row?;
+        if let Some(participant) = find_participant(&mut games, &chat_id, round, user_id) {
+            participant.proofs.push(proof);
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT chat_id, round, user_id, proof_position, msg_id, msg_chat_id \
+         FROM replaced_proofs ORDER BY chat_id, round, user_id, proof_position, position",
+    )?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, i64>(1)?,
+            row.get::<_, i64>(2)?,
+            row.get::<_, i64>(3)? as usize,
+            GameMessage {
+                id: row.get(4)?,
+                chat_id: row.get(5)?,
+            },
+        ))
+    })?;
+    for row in rows {
+        let (chat_id, round, user_id, proof_position, msg) = row?;
+        if let Some(proof) = find_participant(&mut games, &chat_id, round, user_id)
+            .and_then(|participant| participant.proofs.get_mut(proof_position))
+        {
+            proof.replaced.push(msg);
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT chat_id, round, user_id, proof_position, unique_id, duration, size \
+         FROM replaced_files ORDER BY chat_id, round, user_id, proof_position, position",
+    )?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, i64>(1)?,
+            row.get::<_, i64>(2)?,
+            row.get::<_, i64>(3)? as usize,
+            VideoFile {
+                unique_id: row.get(4)?,
+                duration: row.get(5)?,
+                size: row.get(6)?,
+            },
+        ))
+    })?;
+    for row in rows {
+        let (chat_id, round, user_id, proof_position, file) = row?;
+        if let Some(proof) = find_participant(&mut games, &chat_id, round, user_id)
+            .and_then(|participant| participant.proofs.get_mut(proof_position))
+        {
+            proof.replaced_files.push(file);
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT chat_id, user_id, proof_msg_id, proof_msg_chat_id, tricks_proven, \
+         poll_msg_id, poll_msg_chat_id, proven_at, opened_at FROM challenges \
+         ORDER BY chat_id, position",
+    )?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, i64>(1)?,
+            Proof {
+                msg: GameMessage {
+                    id: row.get(2)?,
+                    chat_id: row.get(3)?,
+                },
+                tricks_proven: split_numbers(&row.get::<_, String>(4)?),
+                proven_at: row.get::<_, i64>(7)? as u64,
+                replaced: vec![],
+                file: None,
+                replaced_files: vec![],
+            },
+            GameMessage {
+                id: row.get(5)?,
+                chat_id: row.get(6)?,
+            },
+            row.get::<_, i64>(8)? as u64,
+        ))
+    })?;
+    for row in rows {
+        let (chat_id, user_id, proof, poll_msg, opened_at) = row?;
+        if let Some(game) = games.get_mut(&chat_id) {
+            if let Some((user, participant)) = game
+                .participants
+                .iter()
+                .find(|(user, _)| user.id == user_id)
+            {
+                // The challenged proof is one of the participant's, history included
+                let proof = participant
+                    .proofs
+                    .iter()
+                    .find(|participant_proof| participant_proof.msg == proof.msg)
+                    .cloned()
+                    .unwrap_or(proof);
+                game.proof_challenges.insert(
+                    proof.msg.clone(),
+                    ProofChallenge {
+                        participant: participant.clone(),
+                        user: user.clone(),
+                        proof,
+                        poll_msg,
+                        votes: Default::default(),
+                        opened_at,
+                    },
+                );
+            }
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT chat_id, proof_msg_id, proof_msg_chat_id, user_id, first_name, username, vote \
+         FROM challenge_voters ORDER BY chat_id, proof_msg_id, proof_msg_chat_id, position",
+    )?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            GameMessage {
+                id: row.get(1)?,
+                chat_id: row.get(2)?,
+            },
+            GameUser {
+                id: row.get(3)?,
+                first_name: row.get(4)?,
+                username: row.get(5)?,
+            },
+            row.get::<_, bool>(6)?,
+        ))
+    })?;
+    for row in rows {
+        let (chat_id, proof_msg, voter, vote) = row?;
+        if let Some(challenge) = games
+            .get_mut(&chat_id)
+            .and_then(|game| game.proof_challenges.get_mut(&proof_msg))
+        {
+            challenge.votes.insert(voter, vote);
+        }
+    }
+
+    load_duels(conn, &mut games)?;
+
+    Ok(games)
+}
+
+fn load_duels(conn: &Connection, games: &mut HashMap<String, Game>) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "SELECT chat_id, setter, set_trick_name, set_msg_id, set_msg_chat_id, \
+         landed_trick_name, landed_msg_id, landed_msg_chat_id, poll_msg_id, poll_msg_chat_id, \
+         opened_at FROM duels",
+    )?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        let set_trick = match (row.get(2)?, row.get(3)?, row.get(4)?) {
+            (Some(name), Some(id), Some(chat_id)) => Some(DuelTrick {
+                name,
+                msg: GameMessage { id, chat_id },
+            }),
+            _ => None,
+        };
+        let landed_trick = match (row.get(5)?, row.get(6)?, row.get(7)?) {
+            (Some(name), Some(id), Some(chat_id)) => Some(DuelTrick {
+                name,
+                msg: GameMessage { id, chat_id },
+            }),
+            _ => None,
+        };
+        let dispute = match (row.get(8)?, row.get(9)?) {
+            (Some(id), Some(chat_id)) => Some(DuelDispute {
+                poll_msg: GameMessage { id, chat_id },
+                votes: Default::default(),
+                opened_at: row.get::<_, i64>(10)? as u64,
+            }),
+            _ => None,
+        };
+
+        Ok((
+            row.get::<_, String>(0)?,
+            Duel {
+                players: vec![],
+                setter: row.get::<_, i64>(1)? as usize,
+                set_trick,
+                landed_trick,
+                dispute,
+            },
+        ))
+    })?;
+    for row in rows {
+        let (chat_id, duel) = row?;
+        if let Some(game) = games.get_mut(&chat_id) {
+            game.duel = Some(duel);
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT chat_id, user_id, first_name, username, letters FROM duel_players \
+         ORDER BY chat_id, position",
+    )?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            DuelPlayer {
+                user: GameUser {
+                    id: row.get(1)?,
+                    first_name: row.get(2)?,
+                    username: row.get(3)?,
+                },
+                letters: row.get::<_, i64>(4)? as usize,
+            },
+        ))
+    })?;
+    for row in rows {
+        let (chat_id, player) = row?;
+        if let Some(duel) = games.get_mut(&chat_id).and_then(|game| game.duel.as_mut()) {
+            duel.players.push(player);
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT chat_id, user_id, first_name, username, vote FROM duel_voters \
+         ORDER BY chat_id, position",
+    )?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            GameUser {
+                id: row.get(1)?,
+                first_name: row.get(2)?,
+                username: row.get(3)?,
+            },
+            row.get::<_, bool>(4)?,
+        ))
+    })?;
+    for row in rows {
+        let (chat_id, voter, vote) = row?;
+        if let Some(dispute) = games
+            .get_mut(&chat_id)
+            .and_then(|game| game.duel.as_mut())
+            .and_then(|duel| duel.dispute.as_mut())
+        {
+            dispute.votes.insert(voter, vote);
+        }
+    }
+
+    // A duel is only usable with both of its players
+    for game in games.values_mut() {
+        if game
+            .duel
+            .as_ref()
+            .map_or(false, |duel| duel.players.len() != 2)
+        {
+            game.duel = None;
+        }
+    }
+
+    Ok(())
+}
+
+/// The game of the chat played in the round, the current one or a finished one.
+fn find_game<'a>(
+    games: &'a mut HashMap<String, Game>,
+    chat_id: &str,
+    round: i64,
+) -> Option<&'a mut Game> {
+    games.get_mut(chat_id).and_then(|game| {
+        if round == CURRENT_ROUND {
+            Some(game)
+        } else {
+            game.history.get_mut((round - CURRENT_ROUND - 1) as usize)
+        }
+    })
+}
+
+fn find_participant<'a>(
+    games: &'a mut HashMap<String, Game>,
+    chat_id: &str,
+    round: i64,
+    user_id: i64,
+) -> Option<&'a mut Participant> {
+    find_game(games, chat_id, round).and_then(|game| {
+        game.participants
+            .iter_mut()
+            .find(|(user, _)| user.id == user_id)
+            .map(|(_, participant)| participant)
+    })
+}
+
+fn join_numbers(numbers: &[usize]) -> String {
+    numbers
+        .iter()
+        .map(|number| number.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn split_numbers(s: &str) -> Vec<usize> {
+    s.split(',')
+        .flat_map(|number| number.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexMap;
+
+    use super::*;
+
+    const CHAT_ID: &str = "-100123";
+
+    fn store() -> SqliteStore {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        SqliteStore {
+            conn: Mutex::new(conn),
+        }
+    }
+
+    fn user(id: i64, first_name: &str) -> GameUser {
+        GameUser {
+            id,
+            first_name: first_name.to_owned(),
+            username: None,
+        }
+    }
+
+    fn msg(id: i64) -> GameMessage {
+        GameMessage {
+            id,
+            chat_id: CHAT_ID.parse().unwrap(),
+        }
+    }
+
+    /// A game of a single participant with a proven trick.
+    fn game(trick: &str, finished_at: Option<u64>) -> Game {
+        let mut game = Game {
+            is_started: true,
+            next_trick_id: 2,
+            finished_at,
+            settings: GameSettings {
+                own_tricks: OwnTricks::NotCounted,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut proof = Proof::new(&msg(11), vec![1], 1600000100);
+        proof.replaced.push(msg(10));
+        proof.replaced_files.push(VideoFile {
+            unique_id: "AgADold".to_owned(),
+            duration: Some(6),
+            size: None,
+        });
+        proof.file = Some(VideoFile {
+            unique_id: "AgADann".to_owned(),
+            duration: Some(5),
+            size: Some(1024),
+        });
+        game.participants.insert(
+            GameUser {
+                id: 1,
+                first_name: "Ann".to_owned(),
+                username: None,
+            },
+            Participant {
+                tricks: vec![Trick {
+                    id: 1,
+                    name: trick.to_owned(),
+                    renames: 1,
+                    points: 3,
+                    points_set: true,
+                }],
+                proofs: vec![proof],
+            },
+        );
+        game
+    }
+
+    fn save(store: &SqliteStore, game: &Game) {
+        let mut games = HashMap::new();
+        games.insert(CHAT_ID.to_owned(), game.clone());
+        store.save_chats(&[CHAT_ID.to_owned()], &games).unwrap();
+    }
+
+    fn load(store: &SqliteStore) -> Game {
+        store.load().unwrap().remove(CHAT_ID).unwrap()
+    }
+
+    fn yaml(game: &Game) -> String {
+        serde_yaml::to_string(game).unwrap()
+    }
+
+    fn count(store: &SqliteStore, sql: &str) -> i64 {
+        let conn = store.conn.lock().unwrap();
+        conn.query_row(sql, NO_PARAMS, |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn finished_games_read_back() {
+        let store = store();
+        let mut current = game("kickflip", None);
+        current.history = vec![
+            game("ollie", Some(1600000000)),
+            game("heelflip", Some(1600100000)),
+        ];
+        save(&store, &current);
+
+        assert_eq!(yaml(&load(&store)), yaml(&current));
+        assert_eq!(count(&store, "SELECT COUNT(*) FROM chats"), 3);
+    }
+
+    #[test]
+    fn finished_games_are_written_once() {
+        let store = store();
+        let mut current = game("kickflip", None);
+        current.history = vec![game("ollie", Some(1600000000))];
+        save(&store, &current);
+
+        // A stored round isn't written again, unlike the current game
+        store
+            .conn
+            .lock()
+            .unwrap()
+            .execute_batch("UPDATE tricks SET name = 'stored'")
+            .unwrap();
+        current.history.push(game("heelflip", Some(1600100000)));
+        save(&store, &current);
+
+        let loaded = load(&store);
+        let names = |game: &Game| game.participants[0].tricks[0].name.clone();
+        assert_eq!(names(&loaded), "kickflip");
+        assert_eq!(
+            loaded.history.iter().map(names).collect::<Vec<_>>(),
+            vec!["stored", "heelflip"]
+        );
+
+        // A restored game brings another history, which replaces the stored one
+        current.history = vec![game("shove-it", Some(1500000000))];
+        save(&store, &current);
+        assert_eq!(yaml(&load(&store)), yaml(&current));
+    }
+
+    #[test]
+    fn duel_votes_read_back() {
+        let store = store();
+        let mut current = game("kickflip", None);
+        let mut duel = Duel::new(user(1, "Ann"), user(2, "Bob"));
+        duel.landed_trick = Some(DuelTrick {
+            name: "ollie".to_owned(),
+            msg: msg(20),
+        });
+        let mut votes = IndexMap::new();
+        votes.insert(user(3, "Carl"), false);
+        votes.insert(user(1, "Ann"), true);
+        duel.dispute = Some(DuelDispute {
+            poll_msg: msg(21),
+            votes,
+            opened_at: 1600000200,
+        });
+        current.duel = Some(duel);
+        save(&store, &current);
+
+        assert_eq!(yaml(&load(&store)), yaml(&current));
+    }
+}
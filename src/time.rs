@@ -0,0 +1,10 @@
+//! Unix time the games keep their deadlines, votes and snapshots in.
+
+use chrono::Utc;
+
+pub(crate) const HOUR_SECS: u64 = 60 * 60;
+pub(crate) const DAY_SECS: u64 = 24 * HOUR_SECS;
+
+pub(crate) fn now() -> u64 {
+    Utc::now().timestamp() as u64
+}
@@ -4,10 +4,9 @@ use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use telegram_bot::*;
 
-use crate::MAX_TRICKS;
-use indexmap::set::IndexSet;
+use crate::voting::VotingPolicy;
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub(crate) struct GameMessage {
     pub id: i64,
     pub chat_id: i64,
@@ -16,22 +15,90 @@ pub(crate) struct GameMessage {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) struct Proof {
     pub msg: GameMessage,
-    pub tricks_proven: Vec<usize>, // Contains trick numbers
+    pub tricks_proven: Vec<usize>, // Contains trick ids
+    /// Unix time the video was posted at.
+    pub proven_at: u64,
+    /// Earlier videos of the same tricks, replaced with `/reproof`, oldest first.
+    pub replaced: Vec<GameMessage>,
+    /// Unknown for the proofs added before files were recorded, and for non-video ones.
+    pub file: Option<VideoFile>,
+    /// Known files of the replaced videos, oldest first. They can't prove tricks again.
+    pub replaced_files: Vec<VideoFile>,
 }
 
 impl Proof {
-    pub fn new(msg: &GameMessage, tricks_proven: Vec<usize>) -> Self {
+    pub fn new(msg: &GameMessage, tricks_proven: Vec<usize>, proven_at: u64) -> Self {
         Proof {
             msg: msg.clone(),
             tricks_proven,
+            proven_at,
+            replaced: vec![],
+            file: None,
+            replaced_files: vec![],
+        }
+    }
+}
+
+/// The Telegram file of a proof video, or of a photo or a document. Its unique id stays
+/// the same whenever the clip is forwarded or uploaded again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct VideoFile {
+    pub unique_id: String,
+    /// Length in seconds, not known for documents.
+    pub duration: Option<i64>,
+    /// Size in bytes.
+    pub size: Option<i64>,
+}
+
+impl VideoFile {
+    pub fn of(message: &Message) -> Option<Self> {
+        match &message.kind {
+            MessageKind::Video { data, .. } => Some(VideoFile {
+                unique_id: data.file_unique_id.clone(),
+                duration: Some(data.duration),
+                size: data.file_size,
+            }),
+            MessageKind::VideoNote { data } => Some(VideoFile {
+                unique_id: data.file_unique_id.clone(),
+                duration: Some(data.duration),
+                size: data.file_size,
+            }),
+            MessageKind::Document { data, .. } => Some(VideoFile {
+                unique_id: data.file_unique_id.clone(),
+                duration: None,
+                size: data.file_size,
+            }),
+            // Each size of a photo is a file of its own, the largest one is kept
+            MessageKind::Photo { data, .. } => data
+                .iter()
+                .max_by_key(|size| size.width * size.height)
+                .map(|size| VideoFile {
+                    unique_id: size.file_unique_id.clone(),
+                    duration: None,
+                    size: size.file_size,
+                }),
+            _ => None,
         }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct Trick {
+    /// Number the trick is referred to by, never reused within a game.
+    pub id: usize,
     pub name: String,
-    pub edited: bool,
+    /// How many times the trick was renamed.
+    pub renames: usize,
+    /// Difficulty of the trick, what proving it is worth.
+    pub points: usize,
+    /// Whether the points were set by an admin, a rename keeps them then.
+    pub points_set: bool,
+}
+
+impl Trick {
+    pub fn edited(&self) -> bool {
+        self.renames > 0
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -93,9 +160,213 @@ pub(crate) struct ProofChallenge {
     pub user: GameUser,
     pub proof: Proof,
     pub poll_msg: GameMessage,
-    pub num_yes: usize,
-    pub num_no: usize,
-    pub voters: IndexSet<GameUser>,
+    /// Vote of each voter, `true` to keep the proof, in the order they first voted.
+    pub votes: IndexMap<GameUser, bool>,
+    /// Unix time the poll was posted at, the voting window is counted from it.
+    pub opened_at: u64,
+}
+
+impl ProofChallenge {
+    pub fn num_yes(&self) -> usize {
+        self.votes.values().filter(|vote| **vote).count()
+    }
+
+    pub fn num_no(&self) -> usize {
+        self.votes.values().filter(|vote| !**vote).count()
+    }
+}
+
+/// Letters a duel player collects for the tricks they fail to repeat.
+pub(crate) const SKATE: &[&str] = &["S", "K", "A", "T", "E"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct DuelPlayer {
+    pub user: GameUser,
+    /// How many letters of S.K.A.T.E. the player has.
+    pub letters: usize,
+}
+
+impl DuelPlayer {
+    pub fn letters(&self) -> String {
+        SKATE[..self.letters].join(".")
+    }
+}
+
+/// Trick set or repeated in a duel, along with the video it was done in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct DuelTrick {
+    pub name: String,
+    pub msg: GameMessage,
+}
+
+/// Vote on whether a trick was really repeated in a duel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct DuelDispute {
+    pub poll_msg: GameMessage,
+    /// Vote of each voter, `true` to count the repeat, in the order they first voted.
+    pub votes: IndexMap<GameUser, bool>,
+    /// Unix time the poll was posted at, the voting window is counted from it.
+    pub opened_at: u64,
+}
+
+impl DuelDispute {
+    pub fn num_yes(&self) -> usize {
+        self.votes.values().filter(|vote| **vote).count()
+    }
+
+    pub fn num_no(&self) -> usize {
+        self.votes.values().filter(|vote| !**vote).count()
+    }
+}
+
+/// Head-to-head game of S.K.A.T.E., started with `/duel`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Duel {
+    /// Both players, the one who called the duel first.
+    pub players: Vec<DuelPlayer>,
+    /// Index of the player who sets the tricks.
+    pub setter: usize,
+    /// Trick the other player has to repeat now.
+    pub set_trick: Option<DuelTrick>,
+    /// Latest repeated trick, which can be disputed until the next one is set.
+    pub landed_trick: Option<DuelTrick>,
+    pub dispute: Option<DuelDispute>,
+}
+
+impl Duel {
+    pub fn new(challenger: GameUser, opponent: GameUser) -> Self {
+        Duel {
+            players: vec![
+                DuelPlayer {
+                    user: challenger,
+                    letters: 0,
+                },
+                DuelPlayer {
+                    user: opponent,
+                    letters: 0,
+                },
+            ],
+            setter: 0,
+            set_trick: None,
+            landed_trick: None,
+            dispute: None,
+        }
+    }
+
+    /// Index of the player who repeats the tricks.
+    pub fn responder(&self) -> usize {
+        1 - self.setter
+    }
+
+    pub fn player_index(&self, user: &GameUser) -> Option<usize> {
+        self.players.iter().position(|player| player.user == *user)
+    }
+
+    /// The player who didn't spell S.K.A.T.E., once the other one did.
+    pub fn winner(&self) -> Option<&DuelPlayer> {
+        self.players
+            .iter()
+            .position(|player| player.letters >= SKATE.len())
+            .map(|loser| &self.players[1 - loser])
+    }
+
+    /// Gives the player a letter for a trick they failed to repeat.
+    pub fn give_letter(&mut self, index: usize) {
+        let player = &mut self.players[index];
+        player.letters = (player.letters + 1).min(SKATE.len());
+    }
+
+    /// Passes setting the tricks to the other player.
+    pub fn switch_setter(&mut self) {
+        self.setter = self.responder();
+        self.set_trick = None;
+        self.landed_trick = None;
+    }
+}
+
+/// Whether participants may prove the tricks they added themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) enum OwnTricks {
+    /// Own tricks are proven and scored like any other.
+    Allowed,
+    /// Own tricks can be proven, but only the tricks of others score.
+    NotCounted,
+    /// Proofs of own tricks are rejected.
+    Forbidden,
+}
+
+impl OwnTricks {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OwnTricks::Allowed => "allowed",
+            OwnTricks::NotCounted => "not_counted",
+            OwnTricks::Forbidden => "forbidden",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "allowed" => Some(OwnTricks::Allowed),
+            "not_counted" => Some(OwnTricks::NotCounted),
+            "forbidden" => Some(OwnTricks::Forbidden),
+            _ => None,
+        }
+    }
+}
+
+/// Rules of the game, set per chat with `/settings`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct GameSettings {
+    /// How many tricks each participant may add.
+    pub max_tricks: usize,
+    /// How many times each trick may be renamed.
+    pub max_renames: usize,
+    /// Whether only videos are accepted as proofs.
+    pub videos_only: bool,
+    /// Share of the participants, in percent, that has to vote on a challenge to resolve it.
+    pub quorum_percent: usize,
+    /// Whether proving own tricks is allowed and scored.
+    pub own_tricks: OwnTricks,
+    /// How long a challenge stays open before it's resolved with the votes cast.
+    pub vote_hours: usize,
+    /// Share of the participants, in percent, that has to vote for a challenge closed by
+    /// time to have a verdict. Otherwise the proof stays.
+    pub min_turnout_percent: usize,
+    /// How the votes on a challenge are counted.
+    pub voting: VotingPolicy,
+    /// How many votes decide a challenge with the fixed quorum.
+    pub quorum_votes: usize,
+    /// Whether the owner of a challenged proof may vote on it.
+    pub owner_votes: bool,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        GameSettings {
+            max_tricks: 3,
+            max_renames: 1,
+            videos_only: true,
+            quorum_percent: 50,
+            own_tricks: OwnTricks::Allowed,
+            vote_hours: 24,
+            min_turnout_percent: 20,
+            voting: VotingPolicy::Majority,
+            quorum_votes: 3,
+            owner_votes: true,
+        }
+    }
+}
+
+impl GameSettings {
+    /// Whether that many voters are enough to resolve a challenge.
+    pub fn has_quorum(&self, num_voters: usize, num_participants: usize) -> bool {
+        num_voters * 100 > num_participants * self.quorum_percent
+    }
+
+    /// Whether that many voters are enough for a verdict once the voting time is out.
+    pub fn has_turnout(&self, num_voters: usize, num_participants: usize) -> bool {
+        num_voters > 0 && num_voters * 100 >= num_participants * self.min_turnout_percent
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -103,7 +374,21 @@ pub(crate) struct Game {
     pub participants: IndexMap<GameUser, Participant>,
     pub game_message: Option<GameMessage>,
     pub is_started: bool,
-    pub proof_challenge: Option<ProofChallenge>,
+    /// Votes going on, keyed by the message of the challenged proof.
+    pub proof_challenges: IndexMap<GameMessage, ProofChallenge>,
+    /// Id the next added trick gets.
+    pub next_trick_id: usize,
+    pub settings: GameSettings,
+    /// Unix time the game was ended at with `/endgame`, after which it can't be changed.
+    pub finished_at: Option<u64>,
+    /// Earlier finished games of the chat, oldest first.
+    pub history: Vec<Game>,
+    /// Unix time the game is closed at automatically.
+    pub deadline: Option<u64>,
+    /// How long before the deadline the latest reminder was for, in seconds.
+    pub last_reminder: Option<u64>,
+    /// S.K.A.T.E. duel going on in the chat, or the last one played.
+    pub duel: Option<Duel>,
 }
 
 impl Default for Game {
@@ -112,7 +397,14 @@ impl Default for Game {
             participants: Default::default(),
             game_message: None,
             is_started: false,
-            proof_challenge: Default::default(),
+            proof_challenges: Default::default(),
+            next_trick_id: 1,
+            settings: Default::default(),
+            finished_at: None,
+            history: vec![],
+            deadline: None,
+            last_reminder: None,
+            duel: None,
         }
     }
 }
@@ -122,13 +414,119 @@ impl Game {
         self.is_started
     }
 
+    pub fn finished(&self) -> bool {
+        self.finished_at.is_some()
+    }
+
+    /// Wipes the game, keeping the settings, the history and the duel of the chat.
+    pub fn clear(&mut self) {
+        *self = Game {
+            settings: self.settings.clone(),
+            history: std::mem::take(&mut self.history),
+            duel: self.duel.take(),
+            ..Default::default()
+        };
+    }
+
+    /// Moves the finished game to the history and starts over.
+    pub fn start_new_round(&mut self) {
+        let mut finished = self.clone();
+        finished.history.clear();
+        finished.duel = None;
+        // The polls of a finished game can't be voted in anymore
+        finished.proof_challenges.clear();
+        self.clear();
+        self.history.push(finished);
+    }
+
+    /// Points the participant got for the tricks proven.
+    pub fn points(&self, participant: &Participant) -> usize {
+        participant
+            .proofs
+            .iter()
+            .map(|proof| self.proof_points(participant, proof))
+            .sum()
+    }
+
+    /// Points the proof brings to the participant who posted it.
+    fn proof_points(&self, participant: &Participant, proof: &Proof) -> usize {
+        proof
+            .tricks_proven
+            .iter()
+            .filter(|id| {
+                self.settings.own_tricks != OwnTricks::NotCounted
+                    || !participant.tricks.iter().any(|trick| trick.id == **id)
+            })
+            .flat_map(|id| self.trick_by_id(*id))
+            .map(|trick| trick.points)
+            .sum()
+    }
+
+    /// Unix time of the proof the participant first got to the current points with,
+    /// 0 without any. Proofs posted after that, worth nothing more, don't change it.
+    pub fn points_reached_at(&self, participant: &Participant) -> u64 {
+        let total = self.points(participant);
+        let mut proofs = participant.proofs.iter().collect::<Vec<_>>();
+        proofs.sort_by_key(|proof| proof.proven_at);
+
+        let mut points = 0;
+        for proof in proofs {
+            if points >= total {
+                break;
+            }
+            points += self.proof_points(participant, proof);
+            if points >= total {
+                return proof.proven_at;
+            }
+        }
+
+        0
+    }
+
+    /// Participants from the most points to the least, with their places and points.
+    /// Of those with equal points, the one who got them first is placed higher,
+    /// participants with equal results share the place.
+    pub fn standings(&self) -> Vec<(usize, GameUser, usize)> {
+        let mut results = self
+            .participants
+            .iter()
+            .map(|(user, participant)| {
+                (
+                    user.clone(),
+                    self.points(participant),
+                    self.points_reached_at(participant),
+                )
+            })
+            .collect::<Vec<_>>();
+        results.sort_by(|(_, points_a, at_a), (_, points_b, at_b)| {
+            points_b.cmp(points_a).then(at_a.cmp(at_b))
+        });
+
+        let mut standings: Vec<(usize, GameUser, usize)> = vec![];
+        let mut last_reached_at = 0;
+        for (i, (user, points, reached_at)) in results.into_iter().enumerate() {
+            let place = match standings.last() {
+                Some((place, _, last_points))
+                    if *last_points == points && last_reached_at == reached_at =>
+                {
+                    *place
+                }
+                _ => i + 1,
+            };
+            standings.push((place, user, points));
+            last_reached_at = reached_at;
+        }
+
+        standings
+    }
+
     pub fn participant_tricks(&self, participant: &GameUser) -> Option<Vec<Trick>> {
         self.participants
             .get(participant)
             .map(|participant| participant.tricks.clone())
     }
 
-    pub fn add_trick(&mut self, participant: &User, trick: &str) {
+    pub fn add_trick(&mut self, participant: &User, trick: &str, points: usize) {
         let participant = self
             .participants
             .entry(participant.clone().into())
@@ -138,9 +536,13 @@ impl Game {
             });
 
         (*participant).tricks.push(Trick {
+            id: self.next_trick_id,
             name: trick.to_owned(),
-            edited: false,
+            renames: 0,
+            points,
+            points_set: false,
         });
+        self.next_trick_id += 1;
 
         // Start game if it's not started yet
         if !self.is_started {
@@ -148,32 +550,120 @@ impl Game {
         }
     }
 
-    pub fn trick_by_number(&self, number: usize) -> Option<Trick> {
-        if number == 0 {
-            return None;
+    pub fn trick_by_id(&self, id: usize) -> Option<Trick> {
+        self.participants
+            .values()
+            .flat_map(|participant| participant.tricks.iter())
+            .find(|trick| trick.id == id)
+            .cloned()
+    }
+
+    /// Participant who added the trick.
+    pub fn trick_owner(&self, id: usize) -> Option<GameUser> {
+        self.participants
+            .iter()
+            .find(|(_, participant)| participant.tricks.iter().any(|trick| trick.id == id))
+            .map(|(user, _)| user.clone())
+    }
+
+    fn trick_mut(&mut self, id: usize) -> Option<&mut Trick> {
+        self.participants
+            .values_mut()
+            .flat_map(|participant| participant.tricks.iter_mut())
+            .find(|trick| trick.id == id)
+    }
+
+    /// Renames the trick, which is scored anew by its new name unless an admin set its
+    /// points.
+    pub fn update_trick_name(&mut self, id: usize, new_name: String, points: usize) {
+        if let Some(trick) = self.trick_mut(id) {
+            trick.name = new_name;
+            trick.renames += 1;
+            if !trick.points_set {
+                trick.points = points;
+            }
+        }
+    }
+
+    pub fn set_trick_points(&mut self, id: usize, points: usize) {
+        if let Some(trick) = self.trick_mut(id) {
+            trick.points = points;
+            trick.points_set = true;
+        }
+    }
+
+    /// Whether any proof, of any participant, includes the trick.
+    pub fn is_trick_referenced(&self, id: usize) -> bool {
+        self.participants
+            .values()
+            .flat_map(|participant| participant.proofs.iter())
+            .any(|proof| proof.tricks_proven.contains(&id))
+    }
+
+    pub fn remove_trick(&mut self, id: usize) {
+        for participant in self.participants.values_mut() {
+            participant.tricks.retain(|trick| trick.id != id);
+        }
+    }
+
+    /// Removes the participant along with their tricks, which are also removed from
+    /// the proofs of others. Proofs left without tricks are removed as well.
+    pub fn remove_participant(&mut self, user: &GameUser) -> Option<Participant> {
+        let participant = self.participants.shift_remove(user)?;
+        let removed_tricks = participant
+            .tricks
+            .iter()
+            .map(|trick| trick.id)
+            .collect::<Vec<_>>();
+
+        for other in self.participants.values_mut() {
+            for proof in other.proofs.iter_mut() {
+                proof
+                    .tricks_proven
+                    .retain(|id| !removed_tricks.contains(id));
+            }
+            other.proofs.retain(|proof| !proof.tricks_proven.is_empty());
+        }
+
+        // The votes of the participant don't count anymore
+        for challenge in self.proof_challenges.values_mut() {
+            challenge.votes.shift_remove(user);
+        }
+        if let Some(dispute) = self.duel.as_mut().and_then(|duel| duel.dispute.as_mut()) {
+            dispute.votes.shift_remove(user);
         }
 
-        let number = number - 1; // From human numbers to indices
-        let participant_index = number / MAX_TRICKS;
-        let trick_index = number % MAX_TRICKS;
-        let participant = self.participants.values().nth(participant_index);
-        participant.and_then(|participant| participant.tricks.get(trick_index).cloned())
-    }
-
-    pub fn update_trick_name(&mut self, index: usize, new_name: String) {
-        let participant_index = index / MAX_TRICKS;
-        let trick_index = index % MAX_TRICKS;
-        let mut participant = self.participants.values_mut().nth(participant_index);
-        if let Some(ref mut participant) = &mut participant {
-            if let Some(trick) = participant.tricks.get_mut(trick_index) {
-                // Replace and discard the old value
-                let _ = std::mem::replace(
-                    trick,
-                    Trick {
-                        name: new_name,
-                        edited: true,
-                    },
-                );
+        Some(participant)
+    }
+
+    pub fn remove_proof(&mut self, participant: &GameUser, msg: &GameMessage) -> Option<Proof> {
+        let proofs = &mut self.participants.get_mut(participant)?.proofs;
+        let index = proofs.iter().position(|proof| proof.msg == *msg)?;
+        Some(proofs.remove(index))
+    }
+
+    /// Swaps the video of the proof for a new one, keeping the old one in its history.
+    pub fn replace_proof(
+        &mut self,
+        participant: &GameUser,
+        msg: &GameMessage,
+        new_msg: GameMessage,
+        new_file: Option<VideoFile>,
+    ) {
+        let proof = self
+            .participants
+            .get_mut(participant)
+            .and_then(|participant| {
+                participant
+                    .proofs
+                    .iter_mut()
+                    .find(|proof| proof.msg == *msg)
+            });
+        if let Some(proof) = proof {
+            let old_msg = std::mem::replace(&mut proof.msg, new_msg);
+            proof.replaced.push(old_msg);
+            if let Some(old_file) = std::mem::replace(&mut proof.file, new_file) {
+                proof.replaced_files.push(old_file);
             }
         }
     }
@@ -186,19 +676,21 @@ impl Game {
     ) -> Vec<(usize, String)> {
         let trick_names: Vec<_> = tricks
             .iter()
-            .map(|number| (*number, self.trick_by_number(*number)))
+            .map(|id| (*id, self.trick_by_id(*id)))
             .filter(|(_, trick)| trick.is_some())
-            .map(|(number, trick)| (number, trick.unwrap().name))
+            .map(|(id, trick)| (id, trick.unwrap().name))
             .collect();
 
         if trick_names.is_empty() {
             return trick_names;
         }
 
+        // Only the tricks found are proven, the unknown numbers are left out
+        let tricks = trick_names.iter().map(|(id, _)| *id).collect();
         self.participants.get_mut(participant).map(|participant| {
-            (*participant)
-                .proofs
-                .push(Proof::new(&message.clone().into(), tricks));
+            let mut proof = Proof::new(&message.clone().into(), tricks, message.date as u64);
+            proof.file = VideoFile::of(message);
+            (*participant).proofs.push(proof);
         });
 
         trick_names
@@ -232,12 +724,71 @@ impl Game {
             .is_some()
     }
 
+    /// Points the messages of the game to the chat the old one was migrated to.
+    pub fn move_to_chat(&mut self, chat_id: i64) {
+        if let Some(game_message) = self.game_message.as_mut() {
+            game_message.chat_id = chat_id;
+        }
+
+        let move_proof = |proof: &mut Proof| {
+            proof.msg.chat_id = chat_id;
+            for msg in proof.replaced.iter_mut() {
+                msg.chat_id = chat_id;
+            }
+        };
+
+        for participant in self.participants.values_mut() {
+            participant.proofs.iter_mut().for_each(move_proof);
+        }
+
+        self.proof_challenges = std::mem::take(&mut self.proof_challenges)
+            .into_iter()
+            .map(|(_, mut challenge)| {
+                move_proof(&mut challenge.proof);
+                challenge.poll_msg.chat_id = chat_id;
+                challenge.participant.proofs.iter_mut().for_each(move_proof);
+                (challenge.proof.msg.clone(), challenge)
+            })
+            .collect();
+
+        if let Some(duel) = self.duel.as_mut() {
+            for trick in duel
+                .set_trick
+                .iter_mut()
+                .chain(duel.landed_trick.iter_mut())
+            {
+                trick.msg.chat_id = chat_id;
+            }
+            if let Some(dispute) = duel.dispute.as_mut() {
+                dispute.poll_msg.chat_id = chat_id;
+            }
+        }
+
+        for finished in self.history.iter_mut() {
+            finished.move_to_chat(chat_id);
+        }
+    }
+
     pub fn game_message(&self) -> Option<GameMessage> {
         self.game_message.clone()
     }
 
-    pub fn user_by_index(&self, index: usize) -> Option<GameUser> {
-        self.participants.keys().nth(index).cloned()
+    /// The proof, of any participant, made with the same video file, either now or before
+    /// it was replaced.
+    pub fn find_proof_by_file(&self, file: &VideoFile) -> Option<(GameUser, Proof)> {
+        self.participants.iter().find_map(|(user, participant)| {
+            participant
+                .proofs
+                .iter()
+                .find(|proof| {
+                    proof
+                        .file
+                        .iter()
+                        .chain(&proof.replaced_files)
+                        .any(|proof_file| proof_file.unique_id == file.unique_id)
+                })
+                .map(|proof| (user.clone(), proof.clone()))
+        })
     }
 
     pub fn find_participant_and_proof_by_msg(
@@ -300,3 +851,70 @@ impl From<MessageOrChannelPost> for GameMessage {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: i64, first_name: &str) -> GameUser {
+        GameUser {
+            id,
+            first_name: first_name.to_owned(),
+            username: None,
+        }
+    }
+
+    fn trick(id: usize, name: &str) -> Trick {
+        Trick {
+            id,
+            name: name.to_owned(),
+            renames: 0,
+            points: 2,
+            points_set: false,
+        }
+    }
+
+    fn proof(id: i64, tricks_proven: Vec<usize>, proven_at: u64) -> Proof {
+        Proof::new(&GameMessage { id, chat_id: -1 }, tricks_proven, proven_at)
+    }
+
+    #[test]
+    fn ties_go_to_who_reached_the_points_first() {
+        let (ann, bob) = (user(1, "Ann"), user(2, "Bob"));
+        let mut game = Game {
+            next_trick_id: 3,
+            settings: GameSettings {
+                own_tricks: OwnTricks::NotCounted,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        game.participants.insert(
+            ann.clone(),
+            Participant {
+                tricks: vec![trick(1, "kickflip")],
+                proofs: vec![proof(10, vec![2], 100)],
+            },
+        );
+        game.participants.insert(
+            bob.clone(),
+            Participant {
+                tricks: vec![trick(2, "ollie")],
+                proofs: vec![proof(11, vec![1], 200)],
+            },
+        );
+        let places = |game: &Game| {
+            game.standings()
+                .into_iter()
+                .map(|(place, user, points)| (place, user.first_name, points))
+                .collect::<Vec<_>>()
+        };
+        let expected = vec![(1, "Ann".to_owned(), 2), (2, "Bob".to_owned(), 2)];
+        assert_eq!(places(&game), expected);
+
+        // A later proof worth nothing doesn't change when Ann got her points
+        let ann_proofs = &mut game.participants[0].proofs;
+        ann_proofs.push(proof(12, vec![1], 300));
+        assert_eq!(places(&game), expected);
+    }
+}
@@ -0,0 +1,231 @@
+//! Rules the challenges of proofs are decided by, chosen per chat with `/settings`.
+//!
+//! The policies only count votes, so they don't depend on the messages of the poll.
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::GameSettings;
+
+/// How the vote on a challenged proof is decided.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) enum VotingPolicy {
+    /// Decided once the quorum of the participants voted, a tie keeps the proof.
+    Majority,
+    /// Like the majority, but rejecting the proof takes two thirds of the votes.
+    Supermajority,
+    /// Decided once a fixed number of votes is cast, however many participants there are.
+    FixedQuorum,
+    /// Like the majority, but the vote of an admin decides at once.
+    AdminVeto,
+}
+
+impl VotingPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VotingPolicy::Majority => "majority",
+            VotingPolicy::Supermajority => "supermajority",
+            VotingPolicy::FixedQuorum => "fixed_quorum",
+            VotingPolicy::AdminVeto => "admin_veto",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "majority" => Some(VotingPolicy::Majority),
+            "supermajority" => Some(VotingPolicy::Supermajority),
+            "fixed_quorum" => Some(VotingPolicy::FixedQuorum),
+            "admin_veto" => Some(VotingPolicy::AdminVeto),
+            _ => None,
+        }
+    }
+}
+
+/// Votes cast on a challenge so far.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Tally {
+    pub num_yes: usize,
+    pub num_no: usize,
+    /// How many participants may vote on the challenge.
+    pub num_allowed: usize,
+    /// Vote of an admin, `true` to keep the proof.
+    pub admin_vote: Option<bool>,
+}
+
+impl Tally {
+    pub fn num_votes(&self) -> usize {
+        self.num_yes + self.num_no
+    }
+}
+
+/// Whether the proof is accepted, once the votes are enough to decide.
+pub(crate) fn verdict(settings: &GameSettings, tally: &Tally) -> Option<bool> {
+    if let (VotingPolicy::AdminVeto, Some(vote)) = (settings.voting, tally.admin_vote) {
+        return Some(vote);
+    }
+
+    let is_decided = match settings.voting {
+        // Can't wait for more votes than there are voters
+        VotingPolicy::FixedQuorum => {
+            tally.num_votes() >= settings.quorum_votes.min(tally.num_allowed).max(1)
+        }
+        _ => settings.has_quorum(tally.num_votes(), tally.num_allowed),
+    };
+
+    if is_decided {
+        Some(is_accepted(settings.voting, tally))
+    } else {
+        None
+    }
+}
+
+/// Whether the proof is accepted once the voting time is out, if enough voted for that.
+pub(crate) fn timeout_verdict(settings: &GameSettings, tally: &Tally) -> Option<bool> {
+    if settings.has_turnout(tally.num_votes(), tally.num_allowed) {
+        Some(is_accepted(settings.voting, tally))
+    } else {
+        None
+    }
+}
+
+fn is_accepted(policy: VotingPolicy, tally: &Tally) -> bool {
+    match policy {
+        VotingPolicy::Supermajority => tally.num_no * 3 < tally.num_votes() * 2,
+        _ => tally.num_yes >= tally.num_no,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::challenge::num_allowed_voters;
+    use crate::types::{Game, GameUser, Participant};
+
+    fn settings(voting: VotingPolicy) -> GameSettings {
+        GameSettings {
+            voting,
+            ..Default::default()
+        }
+    }
+
+    fn tally(num_yes: usize, num_no: usize, num_allowed: usize) -> Tally {
+        Tally {
+            num_yes,
+            num_no,
+            num_allowed,
+            admin_vote: None,
+        }
+    }
+
+    #[test]
+    fn majority_keeps_the_proof_on_a_tie() {
+        let settings = settings(VotingPolicy::Majority);
+        assert_eq!(verdict(&settings, &tally(1, 0, 4)), None);
+        assert_eq!(verdict(&settings, &tally(1, 1, 4)), None);
+        assert_eq!(verdict(&settings, &tally(2, 1, 4)), Some(true));
+        assert_eq!(verdict(&settings, &tally(1, 2, 4)), Some(false));
+        assert_eq!(verdict(&settings, &tally(2, 2, 3)), Some(true));
+    }
+
+    #[test]
+    fn supermajority_rejects_with_two_thirds() {
+        let settings = settings(VotingPolicy::Supermajority);
+        assert_eq!(verdict(&settings, &tally(1, 2, 3)), Some(false));
+        assert_eq!(verdict(&settings, &tally(2, 4, 6)), Some(false));
+        // A majority short of two thirds keeps the proof
+        assert_eq!(verdict(&settings, &tally(2, 3, 5)), Some(true));
+        assert_eq!(verdict(&settings, &tally(1, 1, 2)), Some(true));
+    }
+
+    #[test]
+    fn fixed_quorum_is_capped_by_the_voters() {
+        let settings = GameSettings {
+            quorum_votes: 5,
+            ..settings(VotingPolicy::FixedQuorum)
+        };
+        assert_eq!(verdict(&settings, &tally(2, 2, 10)), None);
+        assert_eq!(verdict(&settings, &tally(2, 3, 10)), Some(false));
+        assert_eq!(verdict(&settings, &tally(1, 0, 2)), None);
+        assert_eq!(verdict(&settings, &tally(1, 1, 2)), Some(true));
+        assert_eq!(verdict(&settings, &tally(0, 1, 0)), Some(false));
+    }
+
+    #[test]
+    fn admin_vote_decides_with_the_veto_only() {
+        let veto = |admin_vote| Tally {
+            admin_vote: Some(admin_vote),
+            ..tally(0, 1, 10)
+        };
+        let settings = settings(VotingPolicy::AdminVeto);
+        assert_eq!(verdict(&settings, &veto(true)), Some(true));
+        assert_eq!(verdict(&settings, &veto(false)), Some(false));
+        assert_eq!(verdict(&settings, &tally(0, 1, 10)), None);
+
+        for &policy in &[
+            VotingPolicy::Majority,
+            VotingPolicy::Supermajority,
+            VotingPolicy::FixedQuorum,
+        ] {
+            assert_eq!(verdict(&self::settings(policy), &veto(true)), None);
+        }
+    }
+
+    #[test]
+    fn timeout_verdict_needs_the_turnout() {
+        for &policy in &[
+            VotingPolicy::Majority,
+            VotingPolicy::Supermajority,
+            VotingPolicy::FixedQuorum,
+            VotingPolicy::AdminVeto,
+        ] {
+            let settings = settings(policy);
+            assert_eq!(timeout_verdict(&settings, &tally(0, 0, 0)), None);
+            assert_eq!(timeout_verdict(&settings, &tally(0, 1, 10)), None);
+            assert_eq!(timeout_verdict(&settings, &tally(1, 1, 10)), Some(true));
+            assert_eq!(timeout_verdict(&settings, &tally(0, 2, 10)), Some(false));
+        }
+
+        let settings = settings(VotingPolicy::Supermajority);
+        assert_eq!(timeout_verdict(&settings, &tally(1, 2, 10)), Some(false));
+        assert_eq!(timeout_verdict(&settings, &tally(2, 3, 10)), Some(true));
+    }
+
+    #[test]
+    fn owner_left_out_of_the_voters_lowers_the_quorum() {
+        let user = |id| GameUser {
+            id,
+            first_name: Default::default(),
+            username: None,
+        };
+        let mut game: Game = Default::default();
+        for id in 1..=4 {
+            game.participants.insert(
+                user(id),
+                Participant {
+                    tricks: vec![],
+                    proofs: vec![],
+                },
+            );
+        }
+
+        assert_eq!(num_allowed_voters(&game, &user(1)), 4);
+        assert_eq!(
+            verdict(
+                &game.settings,
+                &tally(0, 2, num_allowed_voters(&game, &user(1)))
+            ),
+            None
+        );
+
+        game.settings.owner_votes = false;
+        assert_eq!(num_allowed_voters(&game, &user(1)), 3);
+        assert_eq!(
+            verdict(
+                &game.settings,
+                &tally(0, 2, num_allowed_voters(&game, &user(1)))
+            ),
+            Some(false)
+        );
+        // Only the owner of a proof is left out
+        assert_eq!(num_allowed_voters(&game, &user(5)), 4);
+    }
+}